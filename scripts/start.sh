@@ -1,6 +1,15 @@
 #! /bin/sh
 #
 # This script will be executed by `cargo run`.
+#
+# Set `HEADLESS=1` to run without a display and with the `isa-debug-exit` device attached. The kernel is booted with
+# `exit_on_boot` and then reports its result through the device, which this script turns into its own exit status:
+# 0 on success, 1 on failure. Test executables built by `cargo test` always run headless.
+#
+# Set `KERNEL_CMDLINE` to pass a command line to the kernel, e.g. `KERNEL_CMDLINE="loglevel=info noapic"`.
+#
+# Set `GDB_SERIAL_PORT` to attach COM2 to a TCP socket on that port, so that the kernel's own GDB stub does not share
+# COM1 with the console, e.g. `KERNEL_CMDLINE="gdb=com2" GDB_SERIAL_PORT=4444`.
 
 set -xe
 
@@ -19,19 +28,68 @@ PROFILE=$(echo "$KERNEL" | cut -d'/' -f3)
 LOG_FILE="target/$(echo "${PROFILE}" | tr '[:lower:]' '[:upper:]').LOG"
 MEMORY_SIZE="4G"
 
-# Copy the needed files into an ISO image.
+# The q35 chipset provides PCI Express, whose configuration space the kernel finds through the MCFG table.
+MACHINE="q35"
+
+# Must match `ISA_DEBUG_EXIT_PORT` and `ExitCode` in the kernel, QEMU exits with `(code << 1) | 1`.
+DEBUG_EXIT_DEVICE="isa-debug-exit,iobase=0xf4,iosize=0x04"
+EXIT_SUCCESS=$(((0x10 << 1) | 1))
+EXIT_FAILURE=$(((0x11 << 1) | 1))
+
+# Test executables carry a hash in their name, unlike the kernel itself.
+case "$(basename "${KERNEL}")" in
+  asmos.elf) ;;
+  *) HEADLESS=1 ;;
+esac
+
+# The kernel only writes to the `isa-debug-exit` device if the command line asks for it; test executables always do.
+if [ "${HEADLESS:-0}" = "1" ]; then
+  KERNEL_CMDLINE="${KERNEL_CMDLINE:-} exit_on_boot"
+fi
+
+# Copy the needed files into an ISO image, the kernel is renamed to the path that `grub.cfg` expects and the
+# command line is appended to its entry.
 mkdir -p "${DEST_ISO_DIR}/${GRUB_DIR}"
-cp "${KERNEL}" "${DEST_ISO_DIR}/${BOOT_DIR}"
-cp "${SRC_ISO_DIR}/${GRUB_DIR}/${GRUB_CONFIG_FILE}" "${DEST_ISO_DIR}/${GRUB_DIR}"
+cp "${KERNEL}" "${DEST_ISO_DIR}/${BOOT_DIR}/asmos.elf"
+sed "s|multiboot2 /boot/asmos.elf.*|multiboot2 /boot/asmos.elf ${KERNEL_CMDLINE:-}|" \
+  "${SRC_ISO_DIR}/${GRUB_DIR}/${GRUB_CONFIG_FILE}" > "${DEST_ISO_DIR}/${GRUB_DIR}/${GRUB_CONFIG_FILE}"
 
 grub-mkrescue -o "${KERNEL_ISO}" "${DEST_ISO_DIR}"
 
+# The second serial port only exists if it is asked for, the variable is expanded unquoted on purpose.
+GDB_SERIAL=""
+if [ -n "${GDB_SERIAL_PORT:-}" ]; then
+  GDB_SERIAL="-serial tcp::${GDB_SERIAL_PORT},server,nowait"
+fi
+
 # Run the created image with QEMU.
+if [ "${HEADLESS:-0}" = "1" ]; then
+  STATUS=0
+  qemu-system-"${ARCH}" \
+    -machine "${MACHINE}" \
+    -m "${MEMORY_SIZE}" \
+    -drive file="${KERNEL_ISO}",format=raw \
+    -no-reboot \
+    -D "${LOG_FILE}" \
+    -d int \
+    -serial stdio \
+    -display none \
+    -device "${DEBUG_EXIT_DEVICE}" || STATUS=$?
+
+  case "${STATUS}" in
+    "${EXIT_SUCCESS}") exit 0 ;;
+    "${EXIT_FAILURE}") exit 1 ;;
+    *) echo "QEMU exited without a result from the kernel (status ${STATUS})" >&2; exit 2 ;;
+  esac
+fi
+
 qemu-system-"${ARCH}" \
+  -machine "${MACHINE}" \
   -m "${MEMORY_SIZE}" \
   -drive file="${KERNEL_ISO}",format=raw \
-  -no-reboot -no-shutdown \
+  -no-reboot \
   -D "${LOG_FILE}" \
   -d int \
   -serial stdio \
+  ${GDB_SERIAL} \
   -s
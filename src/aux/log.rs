@@ -20,15 +20,101 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use core::str;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 use log::{Level, LevelFilter, Metadata, Record, SetLoggerError};
 use log::Log;
+use spin::Mutex;
 
 use crate::{serial_print, serial_println};
 
+pub const MAX_FILTERS: usize = 16;
+pub const MAX_TARGET_LENGTH: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterError {
+    TooManyFilters,
+    TargetTooLong,
+}
+
+/// Target Filter
+///
+/// The level of the records whose target, i.e. the module path that logged them, matches. A filter matches every
+/// target that contains it as a run of whole path segments, so that `apic` matches `asmos::kernel::arch::x86_64::apic`
+/// and `kernel::arch` matches every module below it, but `apic` does not match `ioapic`.
+#[derive(Clone, Copy)]
+struct Filter {
+    target: [u8; MAX_TARGET_LENGTH],
+    length: usize,
+    level: LevelFilter,
+}
+
+impl Filter {
+    fn target(&self) -> &str {
+        str::from_utf8(&self.target[..self.length]).unwrap()
+    }
+
+    fn matches(&self, target: &str) -> bool {
+        let filter = self.target();
+
+        target.match_indices(filter).any(|(position, _)| {
+            let before = &target[..position];
+            let after = &target[position + filter.len()..];
+            (before.is_empty() || before.ends_with("::")) && (after.is_empty() || after.starts_with("::"))
+        })
+    }
+}
+
+struct Filters {
+    filters: [Option<Filter>; MAX_FILTERS],
+}
+
+impl Filters {
+    /// Returns the level of the most specific filter that matches the target.
+    fn level(&self, target: &str) -> Option<LevelFilter> {
+        self.filters.iter()
+                    .flatten()
+                    .filter(|filter| filter.matches(target))
+                    .max_by_key(|filter| filter.length)
+                    .map(|filter| filter.level)
+    }
+
+    fn position(&self, target: &str) -> Option<usize> {
+        self.filters.iter().position(|filter| filter.is_some_and(|filter| filter.target() == target))
+    }
+
+    /// The `log` crate discards records above its maximum level before they reach the logger.
+    fn update_max_level(&self) {
+        let most_verbose = self.filters.iter().flatten().map(|filter| filter.level).fold(level(), Ord::max);
+
+        log::set_max_level(most_verbose);
+    }
+}
+
+const LEVELS: [LevelFilter; 6] = [
+    LevelFilter::Off,
+    LevelFilter::Error,
+    LevelFilter::Warn,
+    LevelFilter::Info,
+    LevelFilter::Debug,
+    LevelFilter::Trace,
+];
+
+/// The default level is kept apart from the filters, so that it can be read without taking their lock.
+static LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Trace as usize);
+static FILTERS: Mutex<Filters> = Mutex::new(Filters { filters: [None; MAX_FILTERS] });
+
 struct Logger;
 
 impl Log for Logger {
-    fn enabled(&self, metadata: &Metadata) -> bool { metadata.level() <= Level::Trace }
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // Interrupt handlers log as well, so rather than waiting for the filters while they are being changed, the
+        // record is judged by the default level.
+        let level = FILTERS.try_lock().and_then(|filters| filters.level(metadata.target())).unwrap_or_else(level);
+
+        metadata.level() <= level
+    }
 
     fn log(&self, record: &Record) {
         if !self.enabled(record.metadata()) { return; }
@@ -46,9 +132,93 @@ impl Log for Logger {
     fn flush(&self) {}
 }
 
+/// Sets the level of the records whose target no filter matches.
+pub fn set_level(level: LevelFilter) {
+    LEVEL.store(level as usize, Ordering::Relaxed);
+    FILTERS.lock().update_max_level();
+}
+
+pub fn level() -> LevelFilter {
+    LEVELS[LEVEL.load(Ordering::Relaxed)]
+}
+
+/// Sets the level of the records of the given target, replacing its previous filter.
+pub fn set_filter(target: &str, level: LevelFilter) -> Result<(), FilterError> {
+    if target.len() > MAX_TARGET_LENGTH {
+        return Err(FilterError::TargetTooLong);
+    }
+
+    let mut filters = FILTERS.lock();
+    let slot = match filters.position(target) {
+        Some(slot) => slot,
+        None => filters.filters.iter().position(Option::is_none).ok_or(FilterError::TooManyFilters)?,
+    };
+
+    let mut filter = Filter { target: [0; MAX_TARGET_LENGTH], length: target.len(), level };
+    filter.target[..target.len()].copy_from_slice(target.as_bytes());
+    filters.filters[slot] = Some(filter);
+    filters.update_max_level();
+
+    Ok(())
+}
+
+/// Removes the filter of the given target and returns `true` if there was one.
+pub fn clear_filter(target: &str) -> bool {
+    let mut filters = FILTERS.lock();
+    let Some(slot) = filters.position(target) else {
+        return false;
+    };
+
+    filters.filters[slot] = None;
+    filters.update_max_level();
+
+    true
+}
+
+/// Calls `f` with the target and level of every filter.
+///
+/// The filters are copied first, so that `f` is free to log or to write to the serial port.
+pub fn for_each_filter<F: FnMut(&str, LevelFilter)>(mut f: F) {
+    let filters = FILTERS.lock().filters;
+
+    for filter in filters.iter().flatten() {
+        f(filter.target(), filter.level);
+    }
+}
+
 pub fn init() -> Result<(), SetLoggerError> {
     log::set_logger(&Logger)?;
-    log::set_max_level(LevelFilter::Trace);
+    FILTERS.lock().update_max_level();
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(target: &str) -> Filter {
+        let mut filter = Filter { target: [0; MAX_TARGET_LENGTH], length: target.len(), level: LevelFilter::Off };
+        filter.target[..target.len()].copy_from_slice(target.as_bytes());
+        filter
+    }
+
+    #[test_case]
+    fn filters_match_whole_path_segments() {
+        assert!(filter("apic").matches("asmos::kernel::arch::x86_64::apic"));
+        assert!(filter("asmos::kernel").matches("asmos::kernel::arch::x86_64::apic"));
+        assert!(!filter("apic").matches("asmos::kernel::arch::x86_64::ioapic"));
+        assert!(filter("apic").matches("asmos::ioapic::apic"));
+    }
+
+    #[test_case]
+    fn most_specific_filter_wins() {
+        let mut filters = Filters { filters: [None; MAX_FILTERS] };
+        filters.filters[0] = Some(Filter { level: LevelFilter::Trace, ..filter("asmos::kernel") });
+        filters.filters[1] = Some(Filter { level: LevelFilter::Error, ..filter("paging") });
+
+        assert_eq!(filters.level("asmos::kernel::arch::x86_64::paging"), Some(LevelFilter::Error));
+        assert_eq!(filters.level("asmos::kernel::arch::x86_64::apic"), Some(LevelFilter::Trace));
+        assert_eq!(filters.level("asmos::testing"), None);
+    }
+}
@@ -0,0 +1,114 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Ring Buffer
+///
+/// A bounded byte queue for a single producer and a single consumer, e.g. an interrupt handler that fills it and the
+/// kernel that drains it. Neither side ever blocks the other: the producer only moves `tail` and the consumer only
+/// moves `head`, both of which count bytes since the buffer was created and wrap around on overflow.
+pub struct RingBuffer<const N: usize> {
+    buffer: UnsafeCell<[u8; N]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+// The slots between `head` and `tail` belong to the consumer, all others to the producer.
+unsafe impl<const N: usize> Sync for RingBuffer<N> {}
+
+impl<const N: usize> RingBuffer<N> {
+    pub const fn new() -> Self {
+        RingBuffer { buffer: UnsafeCell::new([0; N]), head: AtomicUsize::new(0), tail: AtomicUsize::new(0) }
+    }
+
+    fn slot(&self, position: usize) -> *mut u8 {
+        unsafe { self.buffer.get().cast::<u8>().add(position % N) }
+    }
+
+    /// Appends a byte, or returns `false` if the buffer is full. Must only be called by the producer.
+    pub fn push(&self, byte: u8) -> bool {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(self.head.load(Ordering::Acquire)) == N {
+            return false;
+        }
+
+        unsafe { self.slot(tail).write(byte); }
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+
+        true
+    }
+
+    /// Removes the oldest byte, if any. Must only be called by the consumer.
+    pub fn pop(&self) -> Option<u8> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let byte = unsafe { self.slot(head).read() };
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+
+        Some(byte)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tail.load(Ordering::Acquire).wrapping_sub(self.head.load(Ordering::Acquire))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn bytes_come_out_in_order() {
+        let ring = RingBuffer::<4>::new();
+        assert!(ring.push(1) && ring.push(2));
+        assert_eq!(ring.pop(), Some(1));
+        assert_eq!(ring.pop(), Some(2));
+        assert_eq!(ring.pop(), None);
+    }
+
+    #[test_case]
+    fn full_buffer_rejects_bytes() {
+        let ring = RingBuffer::<2>::new();
+        assert!(ring.push(1) && ring.push(2));
+        assert!(!ring.push(3));
+        assert_eq!(ring.len(), 2);
+    }
+
+    #[test_case]
+    fn positions_wrap_around() {
+        let ring = RingBuffer::<3>::new();
+        for byte in 0..10 {
+            assert!(ring.push(byte));
+            assert_eq!(ring.pop(), Some(byte));
+        }
+        assert!(ring.is_empty());
+    }
+}
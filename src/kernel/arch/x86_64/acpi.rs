@@ -0,0 +1,578 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use alloc::vec::Vec;
+use core::{mem, ptr};
+use core::str::Utf8Error;
+
+use spin::Once;
+use x86_64::{PhysAddr, VirtAddr};
+use x86_64::structures::paging::PageTableFlags;
+
+use super::{elf, paging};
+
+const RSDP_SIGNATURE: &str = "RSD PTR ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    NoRsdp,
+    InvalidSignature,
+    InvalidChecksum,
+    InvalidLength,
+    MappingFailed,
+}
+
+/// System Description Table Header (SDT)
+///
+/// The common header of every ACPI table, except for the RSDP and the FACS.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/RSDT
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+impl SdtHeader {
+    /// Checks the signature and that all bytes of the table, including the checksum, sum up to zero.
+    fn validate(&'static self, signature: &[u8; 4]) -> Result<&'static Self, AcpiError> {
+        if &self.signature != signature {
+            return Err(AcpiError::InvalidSignature);
+        }
+
+        let bytes = unsafe { core::slice::from_raw_parts(self as *const _ as *const u8, self.length as usize) };
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(AcpiError::InvalidChecksum);
+        }
+
+        Ok(self)
+    }
+
+    /// Returns the bytes that follow the header, up to the length of the table.
+    pub fn body(&self) -> &[u8] {
+        let start = self as *const _ as *const u8;
+        let length = self.length as usize;
+
+        unsafe { core::slice::from_raw_parts(start.add(mem::size_of::<Self>()), length - mem::size_of::<Self>()) }
+    }
+}
+
+/// Multiple APIC Description Table (MADT)
+///
+/// Describes the interrupt controllers of the system: the local APIC of every processor, the I/O APICs, and how
+/// the legacy ISA interrupts are wired to them.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/MADT
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct Madt {
+    pub header: SdtHeader,
+    pub local_apic_address: u32,
+    pub flags: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum MadtEntry {
+    LocalApic { processor_id: u32, apic_id: u32, flags: u32 },
+    IoApic { id: u8, address: u32, gsi_base: u32 },
+    InterruptSourceOverride { source: u8, gsi: u32, flags: u16 },
+    LocalApicNmi { processor_id: u32, flags: u16, lint: u8 },
+    Unknown { kind: u8 },
+}
+
+impl Madt {
+    /// Processor ID that addresses every processor in a `LocalApicNmi` entry.
+    pub const ALL_PROCESSORS: u32 = u32::MAX;
+
+    /// The processor of a `LocalApic` entry can be used.
+    pub const PROCESSOR_ENABLED: u32 = 1 << 0;
+
+    pub fn entries(&self) -> impl Iterator<Item = MadtEntry> + '_ {
+        // Skip the fields of the MADT that follow the common header.
+        let mut bytes = &self.header.body()[8..];
+
+        core::iter::from_fn(move || {
+            if bytes.len() < 2 || bytes.len() < bytes[1] as usize || bytes[1] < 2 {
+                return None;
+            }
+
+            let (entry, rest) = bytes.split_at(bytes[1] as usize);
+            bytes = rest;
+
+            Some(MadtEntry::parse(entry))
+        })
+    }
+}
+
+impl MadtEntry {
+    fn parse(bytes: &[u8]) -> Self {
+        let u16_at = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        let u32_at = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+
+        match (bytes[0], bytes.len()) {
+            (0, 8..) => MadtEntry::LocalApic {
+                processor_id: bytes[2] as u32,
+                apic_id: bytes[3] as u32,
+                flags: u32_at(4),
+            },
+            (1, 12..) => MadtEntry::IoApic { id: bytes[2], address: u32_at(4), gsi_base: u32_at(8) },
+            (2, 10..) => MadtEntry::InterruptSourceOverride { source: bytes[3], gsi: u32_at(4), flags: u16_at(8) },
+            (4, 6..) => MadtEntry::LocalApicNmi {
+                processor_id: if bytes[2] == 0xFF { Madt::ALL_PROCESSORS } else { bytes[2] as u32 },
+                flags: u16_at(3),
+                lint: bytes[5],
+            },
+            (9, 16..) => MadtEntry::LocalApic { processor_id: u32_at(12), apic_id: u32_at(4), flags: u32_at(8) },
+            (0xA, 12..) => MadtEntry::LocalApicNmi { processor_id: u32_at(4), flags: u16_at(2), lint: bytes[8] },
+            (kind, _) => MadtEntry::Unknown { kind },
+        }
+    }
+}
+
+/// Generic Address Structure (GAS)
+///
+/// Describes the location of a register in one of the address spaces defined by ACPI.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C, packed)]
+pub struct GenericAddress {
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    pub const SYSTEM_MEMORY: u8 = 0;
+    pub const SYSTEM_IO: u8 = 1;
+
+    pub fn is_present(&self) -> bool {
+        self.address != 0
+    }
+}
+
+/// Fixed ACPI Description Table (FADT)
+///
+/// Holds the fixed hardware registers that are needed for power management, e.g. the PM1 control blocks and the
+/// reset register, as well as the location of the DSDT. The signature of the table is `FACP`.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/FADT
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C, packed)]
+pub struct Fadt {
+    pub header: SdtHeader,
+    pub firmware_ctrl: u32,
+    pub dsdt: u32,
+    _reserved0: u8,
+    pub preferred_pm_profile: u8,
+    pub sci_interrupt: u16,
+    pub smi_command_port: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub s4bios_request: u8,
+    pub pstate_control: u8,
+    pub pm1a_event_block: u32,
+    pub pm1b_event_block: u32,
+    pub pm1a_control_block: u32,
+    pub pm1b_control_block: u32,
+    pub pm2_control_block: u32,
+    pub pm_timer_block: u32,
+    pub gpe0_block: u32,
+    pub gpe1_block: u32,
+    pub pm1_event_length: u8,
+    pub pm1_control_length: u8,
+    pub pm2_control_length: u8,
+    pub pm_timer_length: u8,
+    pub gpe0_length: u8,
+    pub gpe1_length: u8,
+    pub gpe1_base: u8,
+    pub cstate_control: u8,
+    pub worst_c2_latency: u16,
+    pub worst_c3_latency: u16,
+    pub flush_size: u16,
+    pub flush_stride: u16,
+    pub duty_offset: u8,
+    pub duty_width: u8,
+    pub day_alarm: u8,
+    pub month_alarm: u8,
+    pub century: u8,
+    pub boot_architecture_flags: u16,
+    _reserved1: u8,
+    pub flags: u32,
+    pub reset_register: GenericAddress,
+    pub reset_value: u8,
+    pub arm_boot_architecture_flags: u16,
+    pub minor_version: u8,
+    pub x_firmware_ctrl: u64,
+    pub x_dsdt: u64,
+    pub x_pm1a_event_block: GenericAddress,
+    pub x_pm1b_event_block: GenericAddress,
+    pub x_pm1a_control_block: GenericAddress,
+    pub x_pm1b_control_block: GenericAddress,
+    pub x_pm2_control_block: GenericAddress,
+    pub x_pm_timer_block: GenericAddress,
+    pub x_gpe0_block: GenericAddress,
+    pub x_gpe1_block: GenericAddress,
+}
+
+impl Fadt {
+    /// The reset register is supported (`RESET_REG_SUP`).
+    pub const RESET_REGISTER_SUPPORTED: u32 = 1 << 10;
+
+    /// Returns the physical address of the DSDT, preferring the 64-bit field.
+    pub fn dsdt_address(&self) -> u64 {
+        match self.x_dsdt {
+            0 => self.dsdt as u64,
+            x_dsdt => x_dsdt,
+        }
+    }
+
+    pub fn supports_reset(&self) -> bool {
+        self.flags & Self::RESET_REGISTER_SUPPORTED != 0 && { self.reset_register }.is_present()
+    }
+}
+
+/// High Precision Event Timer Table (HPET)
+///
+/// OS Dev Wiki: https://wiki.osdev.org/HPET
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct Hpet {
+    pub header: SdtHeader,
+    pub event_timer_block_id: u32,
+    pub base_address: GenericAddress,
+    pub number: u8,
+    pub minimum_tick: u16,
+    pub page_protection: u8,
+}
+
+impl Hpet {
+    pub fn comparator_count(&self) -> u8 {
+        ((self.event_timer_block_id >> 8) & 0x1F) as u8 + 1
+    }
+}
+
+/// PCI Express Memory Mapped Configuration Table (MCFG)
+///
+/// Lists the regions through which the configuration space of the PCI Express segment groups is accessed.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/PCI_Express
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct Mcfg {
+    pub header: SdtHeader,
+    _reserved: u64,
+}
+
+/// Configuration space of the buses `start_bus..=end_bus` of a PCI segment group.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct McfgEntry {
+    pub base_address: u64,
+    pub segment_group: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+    _reserved: u32,
+}
+
+impl Mcfg {
+    /// Returns the allocation entries that follow the table, up to its length.
+    pub fn entries(&self) -> impl Iterator<Item = McfgEntry> + '_ {
+        let entries = &self.header.body()[mem::size_of::<u64>()..];
+        let count = entries.len() / mem::size_of::<McfgEntry>();
+
+        (0..count).map(move |index| unsafe {
+            ptr::read_unaligned((entries.as_ptr() as *const McfgEntry).add(index))
+        })
+    }
+}
+
+/// The tables listed by the root table (RSDT or XSDT) that passed validation.
+static TABLES: Once<Vec<&'static SdtHeader>> = Once::new();
+
+/// Maps the table at the given physical address and returns its header.
+unsafe fn map_table(addr: u64) -> Result<&'static SdtHeader, AcpiError> {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE;
+
+    // The length of the table is only known once its header is mapped.
+    let length = |header: VirtAddr| (*header.as_ptr::<SdtHeader>()).length as usize;
+    let table = paging::map_physical_sized(PhysAddr::new(addr), mem::size_of::<SdtHeader>(), length, flags)
+        .map_err(|_| AcpiError::MappingFailed)?;
+
+    let table = &*table.as_ptr::<SdtHeader>();
+    if (table.length as usize) < mem::size_of::<SdtHeader>() {
+        return Err(AcpiError::InvalidLength);
+    }
+
+    Ok(table)
+}
+
+/// Locates the root table through the RSDP that the bootloader copied into the boot information.
+fn root_table() -> Result<(&'static SdtHeader, usize), AcpiError> {
+    let multiboot_info = elf::multiboot_info();
+
+    // Prefer the XSDT, whose entries are 64 bits wide, but some firmwares leave it out even in revision 2.
+    let (root_addr, width, signature) = match multiboot_info.rsdp_v2_tag().filter(|rsdp| rsdp.xsdt_address() != 0) {
+        Some(rsdp) => {
+            check_rsdp(rsdp.signature(), rsdp.checksum_is_valid())?;
+            log::info!("acpi: RSDP revision {}, OEM {}", rsdp.revision(), rsdp.oem_id().unwrap_or("?").trim_end());
+
+            (rsdp.xsdt_address(), mem::size_of::<u64>(), b"XSDT")
+        }
+        None => {
+            let rsdp = multiboot_info.rsdp_v1_tag().ok_or(AcpiError::NoRsdp)?;
+            check_rsdp(rsdp.signature(), rsdp.checksum_is_valid())?;
+            log::info!("acpi: RSDP revision {}, OEM {}", rsdp.revision(), rsdp.oem_id().unwrap_or("?").trim_end());
+
+            (rsdp.rsdt_address(), mem::size_of::<u32>(), b"RSDT")
+        }
+    };
+
+    let root = unsafe { map_table(root_addr as u64)? }.validate(signature)?;
+
+    Ok((root, width))
+}
+
+fn check_rsdp(signature: Result<&str, Utf8Error>, checksum_is_valid: bool) -> Result<(), AcpiError> {
+    if signature != Ok(RSDP_SIGNATURE) {
+        return Err(AcpiError::InvalidSignature);
+    }
+    if !checksum_is_valid {
+        return Err(AcpiError::InvalidChecksum);
+    }
+
+    Ok(())
+}
+
+fn discover() -> Result<Vec<&'static SdtHeader>, AcpiError> {
+    let (root, width) = root_table()?;
+    let mut tables = Vec::new();
+
+    for entry in root.body().chunks_exact(width) {
+        let addr = entry.iter().rev().fold(0u64, |addr, byte| addr << 8 | *byte as u64);
+
+        // A single broken table must not hide the others.
+        match unsafe { map_table(addr) }.and_then(|table| table.validate(&{ table.signature })) {
+            Ok(table) => tables.push(table),
+            Err(err) => log::warn!("acpi: skipping table at {:#X}: {:?}", addr, err),
+        }
+    }
+
+    // The DSDT is not listed by the root table, but referenced by the FADT.
+    if let Some(fadt) = tables.iter().find(|table| &table.signature == b"FACP").map(|table| read_fadt(table)) {
+        match unsafe { map_table(fadt.dsdt_address()) }.and_then(|table| table.validate(b"DSDT")) {
+            Ok(dsdt) => tables.push(dsdt),
+            Err(err) => log::warn!("acpi: skipping DSDT at {:#X}: {:?}", fadt.dsdt_address(), err),
+        }
+    }
+
+    Ok(tables)
+}
+
+/// Returns the tables that passed validation, in the order of the root table.
+pub fn tables() -> &'static [&'static SdtHeader] {
+    TABLES.get().map_or(&[], Vec::as_slice)
+}
+
+/// Returns the first table with the given signature.
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static SdtHeader> {
+    tables().iter().copied().find(|table| &table.signature == signature)
+}
+
+/// Reinterprets the first table with the given signature, if it is large enough to hold `T`.
+fn typed_table<T>(signature: &[u8; 4]) -> Option<&'static T> {
+    find_table(signature)
+        .filter(|header| header.length as usize >= mem::size_of::<T>())
+        .map(|header| unsafe { &*(header as *const SdtHeader as *const T) })
+}
+
+pub fn madt() -> Option<&'static Madt> {
+    typed_table(b"APIC")
+}
+
+pub fn fadt() -> Option<Fadt> {
+    find_table(b"FACP").map(read_fadt)
+}
+
+fn read_fadt(header: &SdtHeader) -> Fadt {
+    // Older revisions of the FADT are shorter, the fields they lack are left zeroed.
+    let mut fadt = Fadt::default();
+    let length = (header.length as usize).min(mem::size_of::<Fadt>());
+    unsafe {
+        ptr::copy_nonoverlapping(header as *const SdtHeader as *const u8, &mut fadt as *mut Fadt as *mut u8, length);
+    }
+
+    fadt
+}
+
+pub fn dsdt() -> Option<&'static SdtHeader> {
+    find_table(b"DSDT")
+}
+
+/// Returns the `SLP_TYPa` and `SLP_TYPb` values of the given sleep state, e.g. `_S5_`.
+///
+/// Instead of interpreting the AML of the DSDT, its bytes are scanned for the definition of the sleep state object,
+/// i.e. a `Name` whose value is a `Package` that starts with the two values.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/AML
+pub fn sleep_types(state: &[u8; 4]) -> Option<(u8, u8)> {
+    const NAME_OP: u8 = 0x08;
+    const ROOT_CHAR: u8 = b'\\';
+    const PACKAGE_OP: u8 = 0x12;
+
+    let aml = dsdt()?.body();
+
+    let position = (1..(aml.len() + 1).saturating_sub(state.len())).find(|&index| {
+        &aml[index..index + state.len()] == state
+            && (aml[index - 1] == NAME_OP || (index >= 2 && aml[index - 1] == ROOT_CHAR && aml[index - 2] == NAME_OP))
+    })?;
+
+    let package = aml.get(position + state.len()..)?;
+    if *package.first()? != PACKAGE_OP {
+        return None;
+    }
+
+    // The upper two bits of the first byte of `PkgLength` tell how many bytes follow it.
+    let pkg_length_size = 1 + (*package.get(1)? >> 6) as usize;
+    let elements = package.get(1 + pkg_length_size + 1..)?;
+
+    let (slp_typa, size) = aml_integer(elements)?;
+    let (slp_typb, _) = aml_integer(elements.get(size..)?)?;
+
+    Some((slp_typa as u8, slp_typb as u8))
+}
+
+/// Decodes an integer constant and returns it along with the number of bytes it takes.
+fn aml_integer(bytes: &[u8]) -> Option<(u64, usize)> {
+    const ZERO_OP: u8 = 0x00;
+    const ONE_OP: u8 = 0x01;
+    const BYTE_PREFIX: u8 = 0x0A;
+    const WORD_PREFIX: u8 = 0x0B;
+    const DWORD_PREFIX: u8 = 0x0C;
+
+    let size = match *bytes.first()? {
+        ZERO_OP => return Some((0, 1)),
+        ONE_OP => return Some((1, 1)),
+        BYTE_PREFIX => 1,
+        WORD_PREFIX => 2,
+        DWORD_PREFIX => 4,
+        _ => return None,
+    };
+
+    let value = bytes.get(1..1 + size)?.iter().rev().fold(0u64, |value, byte| value << 8 | *byte as u64);
+
+    Some((value, 1 + size))
+}
+
+pub fn hpet() -> Option<&'static Hpet> {
+    typed_table(b"HPET")
+}
+
+pub fn mcfg() -> Option<&'static Mcfg> {
+    typed_table(b"MCFG")
+}
+
+pub fn init() -> Result<(), ()> {
+    let tables = match discover() {
+        Ok(tables) => tables,
+        Err(AcpiError::NoRsdp) => {
+            log::warn!("acpi: no RSDP handed over by the bootloader");
+            Vec::new()
+        }
+        Err(err) => {
+            log::error!("acpi: {:?}", err);
+            return Err(());
+        }
+    };
+
+    for table in &tables {
+        let signature = core::str::from_utf8(&table.signature).unwrap_or("????");
+        let oem_id = core::str::from_utf8(&table.oem_id).unwrap_or("?");
+        log::info!("acpi: {} revision {}, {} bytes, OEM {}", signature, { table.revision }, { table.length },
+            oem_id.trim_end());
+    }
+
+    TABLES.call_once(|| tables);
+
+    if let Some(hpet) = hpet() {
+        log::info!("acpi: HPET at {:#X} with {} comparators", { hpet.base_address.address }, hpet.comparator_count());
+    }
+    for entry in mcfg().into_iter().flat_map(Mcfg::entries) {
+        log::info!("acpi: PCI segment {} buses {}..={} at {:#X}", { entry.segment_group }, entry.start_bus,
+            entry.end_bus, { entry.base_address });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn aml_integer_constants() {
+        assert_eq!(aml_integer(&[0x00]), Some((0, 1)));
+        assert_eq!(aml_integer(&[0x01]), Some((1, 1)));
+        assert_eq!(aml_integer(&[0x0A, 0x05]), Some((5, 2)));
+        assert_eq!(aml_integer(&[0x0B, 0x34, 0x12]), Some((0x1234, 3)));
+        assert_eq!(aml_integer(&[0x0C, 0x78, 0x56, 0x34, 0x12]), Some((0x1234_5678, 5)));
+        assert_eq!(aml_integer(&[0x0A]), None);
+        assert_eq!(aml_integer(&[0x5B]), None);
+    }
+
+    #[test_case]
+    fn madt_and_fadt_are_read() {
+        // QEMU places the local APIC at its architectural address and provides a single I/O APIC.
+        let madt = madt().expect("no MADT");
+        assert_eq!({ madt.local_apic_address }, 0xFEE0_0000);
+        assert_eq!(madt.entries().filter(|entry| matches!(entry, MadtEntry::IoApic { .. })).count(), 1);
+
+        // The DSDT that the FADT points to holds AML, among others the definition of the soft-off state.
+        assert_ne!(fadt().expect("no FADT").dsdt_address(), 0);
+        assert!(!dsdt().expect("no DSDT").body().is_empty());
+        assert!(sleep_types(b"_S5_").is_some());
+    }
+
+    #[test_case]
+    fn hpet_and_mcfg_are_read() {
+        // The q35 machine places the HPET at its conventional address in memory.
+        let hpet = hpet().expect("no HPET");
+        assert_eq!({ hpet.base_address.address_space }, GenericAddress::SYSTEM_MEMORY);
+        assert_eq!({ hpet.base_address.address }, 0xFED0_0000);
+        assert!(hpet.comparator_count() >= 3);
+
+        // Its single PCI Express segment covers all 256 buses through a 256 MiB window.
+        let entries: Vec<McfgEntry> = mcfg().expect("no MCFG").entries().collect();
+        assert_eq!(entries.len(), 1);
+        assert_eq!({ entries[0].base_address }, 0xB000_0000);
+        assert_eq!({ entries[0].segment_group }, 0);
+        assert_eq!((entries[0].start_bus, entries[0].end_bus), (0, 255));
+    }
+}
@@ -0,0 +1,419 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use alloc::vec::Vec;
+use core::arch::x86_64::__cpuid;
+use core::ptr;
+
+use spin::{Mutex, Once};
+use x86_64::{PhysAddr, VirtAddr};
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::structures::paging::PageTableFlags;
+
+use super::acpi::{self, Madt, MadtEntry};
+use super::irq::{self, InterruptController};
+use super::{paging, pic};
+use crate::kernel::cmdline;
+
+/// Vector that the local APIC delivers spurious interrupts on; its lowest four bits must be set on older processors.
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+/// Vector that the local APIC reports internal errors on.
+pub const ERROR_VECTOR: u8 = 0xFE;
+
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_X2APIC_ENABLE: u64 = 1 << 10;
+const APIC_BASE_GLOBAL_ENABLE: u64 = 1 << 11;
+const APIC_BASE_ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+/// The registers of the x2APIC are MSRs starting at this index, one for every 16 bytes of the xAPIC page.
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+const CPUID_APIC: u32 = 1 << 9;
+const CPUID_X2APIC: u32 = 1 << 21;
+
+const SVR_APIC_ENABLE: u32 = 1 << 8;
+
+// The LVT entries of the local APIC and the redirection entries of the I/O APIC share the same layout.
+const DELIVERY_NMI: u32 = 0b100 << 8;
+const ACTIVE_LOW: u32 = 1 << 13;
+const LEVEL_TRIGGERED: u32 = 1 << 15;
+const MASKED: u32 = 1 << 16;
+
+const MPS_POLARITY_ACTIVE_LOW: u16 = 0b11;
+const MPS_TRIGGER_LEVEL: u16 = 0b11 << 2;
+
+/// ISA line that the slave PIC is cascaded to, which never raises interrupts by itself.
+const CASCADE_LINE: u8 = 2;
+
+#[derive(Debug, Clone, Copy)]
+enum Register {
+    Id = 0x020,
+    Version = 0x030,
+    TaskPriority = 0x080,
+    EndOfInterrupt = 0x0B0,
+    SpuriousVector = 0x0F0,
+    ErrorStatus = 0x280,
+    LvtTimer = 0x320,
+    LvtLint0 = 0x350,
+    LvtLint1 = 0x360,
+    LvtError = 0x370,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Mode {
+    XApic(VirtAddr),
+    X2Apic,
+}
+
+/// Local Advanced Programmable Interrupt Controller (LAPIC)
+///
+/// Every processor has its own local APIC, which receives interrupts from the I/O APICs and other processors. In
+/// xAPIC mode, its registers are memory mapped, whereas in x2APIC mode they are accessed through MSRs.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/APIC
+pub struct LocalApic {
+    mode: Mode,
+}
+
+impl LocalApic {
+    fn read(&self, register: Register) -> u32 {
+        match self.mode {
+            Mode::XApic(base) => unsafe { ptr::read_volatile((base + register as u64).as_ptr::<u32>()) },
+            Mode::X2Apic => unsafe { Msr::new(X2APIC_MSR_BASE + (register as u32 >> 4)).read() as u32 },
+        }
+    }
+
+    fn write(&self, register: Register, value: u32) {
+        match self.mode {
+            Mode::XApic(base) => unsafe { ptr::write_volatile((base + register as u64).as_mut_ptr::<u32>(), value) },
+            Mode::X2Apic => unsafe { Msr::new(X2APIC_MSR_BASE + (register as u32 >> 4)).write(value as u64) },
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        match self.mode {
+            Mode::XApic(_) => self.read(Register::Id) >> 24,
+            Mode::X2Apic => self.read(Register::Id),
+        }
+    }
+
+    pub fn version(&self) -> u8 {
+        self.read(Register::Version) as u8
+    }
+
+    pub fn is_x2apic(&self) -> bool {
+        matches!(self.mode, Mode::X2Apic)
+    }
+
+    pub fn end_of_interrupt(&self) {
+        self.write(Register::EndOfInterrupt, 0);
+    }
+
+    /// Returns the errors that occurred since the last call.
+    pub fn error_status(&self) -> u32 {
+        // The register is only updated when it is written to.
+        self.write(Register::ErrorStatus, 0);
+        self.read(Register::ErrorStatus)
+    }
+
+    fn enable(&self, madt: &Madt, processor_id: Option<u32>) {
+        self.write(Register::LvtTimer, MASKED);
+        self.write(Register::LvtLint0, MASKED);
+        self.write(Register::LvtLint1, MASKED);
+        self.write(Register::LvtError, ERROR_VECTOR as u32);
+
+        // The firmware tells which of the local interrupt pins the NMI is wired to.
+        for entry in madt.entries() {
+            let MadtEntry::LocalApicNmi { processor_id: target, flags, lint } = entry else { continue; };
+            if target != Madt::ALL_PROCESSORS && Some(target) != processor_id {
+                continue;
+            }
+
+            let mut lvt = DELIVERY_NMI;
+            if flags & MPS_POLARITY_ACTIVE_LOW == MPS_POLARITY_ACTIVE_LOW {
+                lvt |= ACTIVE_LOW;
+            }
+
+            match lint {
+                0 => self.write(Register::LvtLint0, lvt),
+                1 => self.write(Register::LvtLint1, lvt),
+                _ => log::warn!("apic: NMI wired to unknown LINT{}", lint),
+            }
+        }
+
+        // Accept interrupts of every priority and software enable the local APIC.
+        self.write(Register::TaskPriority, 0);
+        self.write(Register::SpuriousVector, SVR_APIC_ENABLE | SPURIOUS_VECTOR as u32);
+
+        self.error_status();
+    }
+}
+
+/// Input/Output Advanced Programmable Interrupt Controller (I/O APIC)
+///
+/// Routes the interrupts of external devices to the local APICs. Each input pin is identified by its global system
+/// interrupt (GSI) and is programmed through an entry of the redirection table, which holds the vector, the target
+/// processor, the polarity, the trigger mode, and a mask bit.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/IOAPIC
+pub struct IoApic {
+    id: u8,
+    base: VirtAddr,
+    gsi_base: u32,
+    redirection_count: u32,
+}
+
+impl IoApic {
+    const IOREGSEL: u64 = 0x00;
+    const IOWIN: u64 = 0x10;
+
+    const IOAPICVER: u32 = 0x01;
+    const IOREDTBL: u32 = 0x10;
+
+    unsafe fn new(id: u8, addr: PhysAddr, gsi_base: u32) -> Option<Self> {
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE
+            | PageTableFlags::NO_EXECUTE;
+        let base = paging::map_physical(addr, 0x20, flags).ok()?;
+
+        let mut io_apic = IoApic { id, base, gsi_base, redirection_count: 0 };
+        io_apic.redirection_count = ((io_apic.read(Self::IOAPICVER) >> 16) & 0xFF) + 1;
+
+        Some(io_apic)
+    }
+
+    fn read(&self, register: u32) -> u32 {
+        unsafe {
+            ptr::write_volatile((self.base + Self::IOREGSEL).as_mut_ptr::<u32>(), register);
+            ptr::read_volatile((self.base + Self::IOWIN).as_ptr::<u32>())
+        }
+    }
+
+    fn write(&self, register: u32, value: u32) {
+        unsafe {
+            ptr::write_volatile((self.base + Self::IOREGSEL).as_mut_ptr::<u32>(), register);
+            ptr::write_volatile((self.base + Self::IOWIN).as_mut_ptr::<u32>(), value);
+        }
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.redirection_count).contains(&gsi)
+    }
+
+    fn redirection(&self, gsi: u32) -> u64 {
+        let register = Self::IOREDTBL + 2 * (gsi - self.gsi_base);
+        (self.read(register + 1) as u64) << 32 | self.read(register) as u64
+    }
+
+    fn set_redirection(&self, gsi: u32, entry: u64) {
+        let register = Self::IOREDTBL + 2 * (gsi - self.gsi_base);
+
+        // Write the upper half first, so that the entry is never unmasked with a stale destination.
+        self.write(register + 1, (entry >> 32) as u32);
+        self.write(register, entry as u32);
+    }
+}
+
+/// Describes how an ISA interrupt line is wired to an I/O APIC.
+#[derive(Debug, Clone, Copy)]
+struct Route {
+    gsi: u32,
+    flags: u16,
+}
+
+struct Router {
+    io_apics: Vec<IoApic>,
+    routes: [Option<Route>; irq::LEGACY_IRQ_COUNT as usize],
+}
+
+impl Router {
+    fn io_apic(&self, gsi: u32) -> Option<&IoApic> {
+        self.io_apics.iter().find(|io_apic| io_apic.handles(gsi))
+    }
+
+    fn route(&self, line: u8, destination: u32) {
+        let Some(Route { gsi, flags }) = self.routes[line as usize] else { return; };
+        let Some(io_apic) = self.io_apic(gsi) else {
+            log::warn!("apic: no I/O APIC handles GSI {}", gsi);
+            return;
+        };
+
+        // ISA interrupts are active high and edge triggered, unless overridden by the firmware.
+        let mut entry = irq::legacy_vector(line) as u64 | MASKED as u64 | (destination as u64) << 56;
+        if flags & MPS_POLARITY_ACTIVE_LOW == MPS_POLARITY_ACTIVE_LOW {
+            entry |= ACTIVE_LOW as u64;
+        }
+        if flags & MPS_TRIGGER_LEVEL == MPS_TRIGGER_LEVEL {
+            entry |= LEVEL_TRIGGERED as u64;
+        }
+
+        io_apic.set_redirection(gsi, entry);
+    }
+
+    fn set_masked(&self, line: u8, masked: bool) {
+        let Some(Route { gsi, .. }) = self.routes.get(line as usize).copied().flatten() else {
+            log::warn!("apic: ISA line {} is not routed", line);
+            return;
+        };
+        let Some(io_apic) = self.io_apic(gsi) else { return; };
+
+        let entry = io_apic.redirection(gsi);
+        io_apic.set_redirection(gsi, if masked { entry | MASKED as u64 } else { entry & !(MASKED as u64) });
+    }
+}
+
+static LOCAL_APIC: Once<LocalApic> = Once::new();
+static ROUTER: Once<Mutex<Router>> = Once::new();
+
+/// Interrupt controller backed by the local APIC and the I/O APICs.
+pub struct Apic;
+
+impl InterruptController for Apic {
+    fn is_spurious(&self, _vector: u8) -> bool {
+        // Spurious interrupts are delivered on their own vector, see `on_spurious`.
+        false
+    }
+
+    fn end_of_interrupt(&self, _vector: u8) {
+        if let Some(local_apic) = LOCAL_APIC.get() {
+            local_apic.end_of_interrupt();
+        }
+    }
+
+    fn set_masked(&self, line: u8, masked: bool) {
+        if let Some(router) = ROUTER.get() {
+            router.lock().set_masked(line, masked);
+        }
+    }
+}
+
+/// Handles the spurious interrupts of the local APIC, which must not be acknowledged.
+pub extern "x86-interrupt" fn on_spurious(_stack_frame: InterruptStackFrame) {}
+
+/// Reports the internal errors of the local APIC, e.g. an interrupt that was sent to an illegal vector.
+pub extern "x86-interrupt" fn on_error(_stack_frame: InterruptStackFrame) {
+    if let Some(local_apic) = LOCAL_APIC.get() {
+        log::error!("apic: error status {:#010X}", local_apic.error_status());
+        local_apic.end_of_interrupt();
+    }
+}
+
+/// Returns whether the processor has a local APIC and whether it supports x2APIC mode.
+fn detect() -> (bool, bool) {
+    let cpuid = __cpuid(1);
+
+    (cpuid.edx & CPUID_APIC != 0, cpuid.ecx & CPUID_X2APIC != 0)
+}
+
+pub fn local_apic() -> Option<&'static LocalApic> {
+    LOCAL_APIC.get()
+}
+
+pub fn init() -> Result<(), ()> {
+    if !cmdline::config().apic {
+        log::info!("apic: disabled by `noapic`, keeping the PIC");
+        return Ok(());
+    }
+
+    let (apic_supported, x2apic_supported) = detect();
+    if !apic_supported {
+        log::warn!("apic: not supported, keeping the PIC");
+        return Ok(());
+    }
+
+    let Some(madt) = acpi::madt() else {
+        log::warn!("apic: no MADT, keeping the PIC");
+        return Ok(());
+    };
+
+    // Mask the PIC before the local APIC is enabled, otherwise both of them would deliver the legacy interrupts.
+    pic::disable();
+
+    let mut apic_base = Msr::new(IA32_APIC_BASE);
+    let base = unsafe { apic_base.read() };
+
+    // x2APIC mode can only be entered once the local APIC is globally enabled.
+    unsafe { apic_base.write(base | APIC_BASE_GLOBAL_ENABLE); }
+
+    let mode = if x2apic_supported {
+        unsafe { apic_base.write(base | APIC_BASE_GLOBAL_ENABLE | APIC_BASE_X2APIC_ENABLE); }
+        Mode::X2Apic
+    } else {
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE
+            | PageTableFlags::NO_EXECUTE;
+        let addr = PhysAddr::new(base & APIC_BASE_ADDRESS_MASK);
+        Mode::XApic(unsafe { paging::map_physical(addr, 0x400, flags).map_err(|_| ())? })
+    };
+
+    let local_apic = LOCAL_APIC.call_once(|| LocalApic { mode });
+    let apic_id = local_apic.id();
+    let processor_id = madt.entries().find_map(|entry| match entry {
+        MadtEntry::LocalApic { processor_id, apic_id: id, .. } if id == apic_id => Some(processor_id),
+        _ => None,
+    });
+
+    local_apic.enable(madt, processor_id);
+
+    // Every ISA line is identity mapped to a GSI, unless the firmware overrides it.
+    let mut router = Router { io_apics: Vec::new(), routes: [None; irq::LEGACY_IRQ_COUNT as usize] };
+    for line in (0..irq::LEGACY_IRQ_COUNT).filter(|line| *line != CASCADE_LINE) {
+        router.routes[line as usize] = Some(Route { gsi: line as u32, flags: 0 });
+    }
+
+    for entry in madt.entries() {
+        match entry {
+            MadtEntry::IoApic { id, address, gsi_base } => {
+                let io_apic = unsafe { IoApic::new(id, PhysAddr::new(address as u64), gsi_base).ok_or(())? };
+                router.io_apics.push(io_apic);
+            }
+            MadtEntry::InterruptSourceOverride { source, gsi, flags } if source < irq::LEGACY_IRQ_COUNT => {
+                // The GSI is no longer reachable through the ISA line it would be identity mapped to.
+                for route in router.routes.iter_mut().filter(|route| matches!(route, Some(r) if r.gsi == gsi)) {
+                    *route = None;
+                }
+                router.routes[source as usize] = Some(Route { gsi, flags });
+            }
+            MadtEntry::Unknown { kind } => log::trace!("apic: ignoring MADT entry of type {}", kind),
+            _ => {}
+        }
+    }
+
+    for line in 0..irq::LEGACY_IRQ_COUNT {
+        router.route(line, apic_id);
+    }
+
+    for io_apic in &router.io_apics {
+        log::info!("apic: I/O APIC {} at {:#X}, GSIs {}..{}", io_apic.id, io_apic.base.as_u64(), io_apic.gsi_base,
+            io_apic.gsi_base + io_apic.redirection_count);
+    }
+
+    ROUTER.call_once(|| Mutex::new(router));
+    irq::set_controller(&Apic);
+
+    let processors = madt.entries().filter(|entry| {
+        matches!(entry, MadtEntry::LocalApic { flags, .. } if flags & Madt::PROCESSOR_ENABLED != 0)
+    });
+    log::info!("apic: local APIC {} in {} mode, version {:#X}, {} processors enabled", apic_id,
+        if local_apic.is_x2apic() { "x2APIC" } else { "xAPIC" }, local_apic.version(), processors.count());
+
+    Ok(())
+}
@@ -0,0 +1,113 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use core::arch::asm;
+
+use rustc_demangle::demangle;
+use x86_64::VirtAddr;
+
+use super::{paging, symbols};
+use crate::serial_println;
+
+/// Frames beyond this depth are not printed, in case the chain is corrupted into a cycle.
+const MAX_DEPTH: usize = 64;
+
+fn is_readable(addr: u64) -> bool {
+    VirtAddr::try_new(addr).is_ok_and(|addr| paging::walk(addr).is_mapped())
+}
+
+/// Walks the chain of frame pointers that starts at the given RBP and calls `f` with every return address.
+///
+/// Every function saves the RBP of its caller right below its return address and points RBP at it, since the
+/// kernel is built with frame pointers. The prelude clears RBP before it calls `k_main`, which ends the chain.
+pub fn walk<F: FnMut(usize, u64)>(mut rbp: u64, mut f: F) {
+    for depth in 0..MAX_DEPTH {
+        if rbp == 0 || rbp % 8 != 0 || !is_readable(rbp) || !is_readable(rbp + 8) {
+            break;
+        }
+
+        let frame = rbp as *const u64;
+        let (caller_rbp, return_addr) = unsafe { (frame.read(), frame.add(1).read()) };
+        if return_addr == 0 {
+            break;
+        }
+
+        f(depth, return_addr);
+
+        // The frames of the callers lie above, so anything else means that the chain is corrupted.
+        if caller_rbp <= rbp {
+            break;
+        }
+        rbp = caller_rbp;
+    }
+}
+
+/// Prints the stack trace that starts at the given RBP.
+pub fn print_from(rbp: u64) {
+    serial_println!("stack trace:");
+
+    walk(rbp, |depth, return_addr| {
+        // The return address points past the call, which might already be the first byte of the next function.
+        match symbols::resolve(return_addr - 1) {
+            Some((name, offset)) => {
+                serial_println!("  #{:<2} {:#018X} {:#}+{:#X}", depth, return_addr, demangle(name), offset + 1);
+            }
+            None => serial_println!("  #{:<2} {:#018X} <unknown>", depth, return_addr),
+        }
+    });
+}
+
+/// Prints the stack trace of the caller.
+#[inline(never)]
+pub fn print() {
+    let rbp: u64;
+    unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack)); }
+
+    print_from(rbp);
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    #[test_case]
+    fn functions_are_resolved() {
+        let (name, offset) = symbols::resolve(print as *const () as u64 + 1).expect("no symbol for print");
+
+        assert!(format!("{:#}", demangle(name)).ends_with("backtrace::print"));
+        assert_eq!(offset, 1);
+    }
+
+    #[test_case]
+    fn walk_reaches_k_main() {
+        let rbp: u64;
+        unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack)); }
+
+        let mut names = Vec::new();
+        walk(rbp, |_, return_addr| names.extend(symbols::resolve(return_addr - 1).map(|(name, _)| name)));
+
+        assert!(names.contains(&"k_main"));
+    }
+}
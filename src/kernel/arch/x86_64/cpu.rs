@@ -0,0 +1,170 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use core::arch::asm;
+use core::fmt;
+
+use rustc_demangle::demangle;
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+use x86_64::registers::rflags;
+use x86_64::registers::segmentation::{Segment, CS, SS};
+use x86_64::structures::idt::InterruptStackFrame;
+
+use super::symbols;
+
+/// General Purpose Registers
+///
+/// The registers of the interrupted code, in the order that an entry stub pushes them onto the stack.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GeneralRegisters {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+}
+
+/// CPU State
+///
+/// A snapshot of the registers that describe where the processor was and in which environment it was running. The
+/// general purpose registers are only known if an entry stub saved them before any compiled code could clobber them.
+#[derive(Debug, Clone, Copy)]
+pub struct CpuState {
+    pub rip: u64,
+    pub rsp: u64,
+    pub rflags: u64,
+    pub cs: u16,
+    pub ss: u16,
+    pub cr0: u64,
+    pub cr2: u64,
+    pub cr3: u64,
+    pub cr4: u64,
+    pub vector: Option<u8>,
+    pub error_code: Option<u64>,
+    pub registers: Option<GeneralRegisters>,
+}
+
+impl CpuState {
+    /// Takes a snapshot of the caller.
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let (rip, rsp): (u64, u64);
+        unsafe {
+            asm!("lea {}, [rip]", out(reg) rip, options(nomem, nostack, preserves_flags));
+            asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags));
+        }
+
+        CpuState {
+            rip,
+            rsp,
+            rflags: rflags::read_raw(),
+            cs: CS::get_reg().0,
+            ss: SS::get_reg().0,
+            cr0: Cr0::read_raw(),
+            cr2: Cr2::read_raw(),
+            cr3: Cr3::read_raw().0.start_address().as_u64(),
+            cr4: Cr4::read_raw(),
+            vector: None,
+            error_code: None,
+            registers: None,
+        }
+    }
+
+    /// Takes a snapshot of the code that was interrupted by the given exception, whose registers an entry stub saved.
+    pub fn from_exception(
+        stack_frame: &InterruptStackFrame,
+        vector: u8,
+        error_code: Option<u64>,
+        registers: GeneralRegisters,
+    ) -> Self {
+        CpuState {
+            rip: stack_frame.instruction_pointer.as_u64(),
+            rsp: stack_frame.stack_pointer.as_u64(),
+            rflags: stack_frame.cpu_flags,
+            cs: stack_frame.code_segment as u16,
+            ss: stack_frame.stack_segment as u16,
+            vector: Some(vector),
+            error_code,
+            registers: Some(registers),
+            ..Self::capture()
+        }
+    }
+}
+
+impl fmt::Display for CpuState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(vector) = self.vector {
+            write!(f, "vector={:#04X}", vector)?;
+            if let Some(error_code) = self.error_code {
+                write!(f, " error_code={:#X}", error_code)?;
+            }
+            writeln!(f)?;
+        }
+
+        write!(f, "RIP={:#018X}", self.rip)?;
+        match symbols::resolve(self.rip) {
+            Some((name, offset)) => writeln!(f, " {:#}+{:#X}", demangle(name), offset)?,
+            None => writeln!(f)?,
+        }
+        writeln!(f, "RSP={:#018X} RFLAGS={:#018X} CS={:#06X} SS={:#06X}", self.rsp, self.rflags, self.cs, self.ss)?;
+        writeln!(f, "CR0={:#018X} CR2={:#018X}", self.cr0, self.cr2)?;
+        write!(f, "CR3={:#018X} CR4={:#018X}", self.cr3, self.cr4)?;
+
+        if let Some(regs) = &self.registers {
+            writeln!(f)?;
+            writeln!(f, "RAX={:#018X} RBX={:#018X} RCX={:#018X}", regs.rax, regs.rbx, regs.rcx)?;
+            writeln!(f, "RDX={:#018X} RSI={:#018X} RDI={:#018X}", regs.rdx, regs.rsi, regs.rdi)?;
+            writeln!(f, "RBP={:#018X} R8 ={:#018X} R9 ={:#018X}", regs.rbp, regs.r8, regs.r9)?;
+            writeln!(f, "R10={:#018X} R11={:#018X} R12={:#018X}", regs.r10, regs.r11, regs.r12)?;
+            write!(f, "R13={:#018X} R14={:#018X} R15={:#018X}", regs.r13, regs.r14, regs.r15)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use x86_64::registers::control::Cr0Flags;
+
+    use super::*;
+
+    #[test_case]
+    fn capture_reads_the_paging_state() {
+        let state = CpuState::capture();
+
+        assert!(Cr0Flags::from_bits_truncate(state.cr0).contains(Cr0Flags::PAGING));
+        assert_eq!(state.cr3, Cr3::read().0.start_address().as_u64());
+        assert!(symbols::resolve(state.rip).is_some());
+    }
+}
@@ -21,6 +21,7 @@
 // SOFTWARE.
 
 use core::ops::Range;
+use core::ptr::{self, addr_of};
 
 use multiboot2::BootInformation;
 
@@ -36,23 +37,61 @@ extern "C" {
     static _PRELUDE_REGION_END: u8;
 
     static _KERNEL_OFFSET: u8;
+    static _KERNEL_WINDOW_SIZE: u8;
 
     static _KERNEL_REGION_BEGIN: u8;
     static _KERNEL_REGION_END: u8;
+
+    static _TEXT_BEGIN: u8;
+    static _TEXT_END: u8;
+
+    static _RODATA_BEGIN: u8;
+    static _RODATA_END: u8;
+
+    static _DATA_BEGIN: u8;
+    static _DATA_END: u8;
+
+    static _BSS_BEGIN: u8;
+    static _BSS_END: u8;
+
+    static _GOT_BEGIN: u8;
+    static _GOT_END: u8;
+
+    static _STACK_GUARD_PAGE: u8;
+    static _STACK_BOTTOM: u8;
+    static _STACK_TOP: u8;
 }
 
 static mut MULTIBOOT_INFO: Option<BootInformation> = None;
 
 pub fn init(boot_info_addr: usize) -> Result<(), ()> {
+    // The prelude hands over the higher half alias of the boot information. Loading it with the kernel offset makes
+    // the tags that point to other physical structures (e.g. the string table of the ELF sections) resolve through
+    // the higher half as well, so that nothing depends on the identity mapping anymore.
+    let offset = kernel_offset();
+
+    // Only the kernel window is mapped in the higher half. The prelude copies the boot information into the kernel
+    // image wherever the bootloader put it, unless it is too large for the copy, in which case it is only usable if
+    // it happens to lie within the window. Its header starts with the total size as a `u32`.
+    let window = kernel_window();
+    if !window.contains(&boot_info_addr) || window.end - boot_info_addr < 8 {
+        return Err(());
+    }
+
+    let total_size = unsafe { ptr::read_volatile(boot_info_addr as *const u32) } as usize;
+    if total_size > window.end - boot_info_addr {
+        return Err(());
+    }
+
     unsafe {
-        MULTIBOOT_INFO = multiboot2::load(boot_info_addr).ok();
+        MULTIBOOT_INFO = Some(multiboot2::load_with_offset(boot_info_addr - offset, offset).map_err(|_| ())?);
     }
 
     Ok(())
 }
 
 pub fn multiboot_info() -> &'static BootInformation {
-    unsafe { MULTIBOOT_INFO.as_ref().unwrap() }
+    unsafe { (*addr_of!(MULTIBOOT_INFO)).as_ref().unwrap() }
 }
 
 pub fn multiboot_region() -> Range<usize> {
@@ -60,6 +99,31 @@ pub fn multiboot_region() -> Range<usize> {
     multiboot_info.start_address()..multiboot_info.end_address()
 }
 
+/// Returns the command line that the bootloader passed to the kernel, if any.
+pub fn command_line() -> Option<&'static str> {
+    let command_line = multiboot_info().command_line_tag()?.command_line().ok()?;
+
+    Some(command_line.trim_end_matches('\0'))
+}
+
+/// Returns the physical region of a section that is not part of the kernel image, e.g. the symbol table, if the
+/// bootloader has loaded it into memory.
+fn loaded_section(name: &str) -> Option<Range<usize>> {
+    let elf_sections = multiboot_info().elf_sections_tag()?;
+    let section = elf_sections.sections()
+                              .find(|section| !section.is_allocated() && section.name() == Ok(name))?;
+
+    (section.start_address() != 0).then(|| section.start_address() as usize..section.end_address() as usize)
+}
+
+pub fn symbol_table_region() -> Option<Range<usize>> {
+    loaded_section(".symtab")
+}
+
+pub fn string_table_region() -> Option<Range<usize>> {
+    loaded_section(".strtab")
+}
+
 pub fn reserved_region() -> Range<usize> {
     foreign_symbol!(_RESERVED_REGION_BEGIN)..foreign_symbol!(_RESERVED_REGION_END)
 }
@@ -72,6 +136,39 @@ pub fn kernel_offset() -> usize {
     foreign_symbol!(_KERNEL_OFFSET)
 }
 
+/// Returns the region of the higher half through which the prelude maps the low physical memory.
+pub fn kernel_window() -> Range<usize> {
+    kernel_offset()..kernel_offset() + foreign_symbol!(_KERNEL_WINDOW_SIZE)
+}
+
 pub fn kernel_region() -> Range<usize> {
     foreign_symbol!(_KERNEL_REGION_BEGIN)..foreign_symbol!(_KERNEL_REGION_END)
 }
+
+pub fn text_region() -> Range<usize> {
+    foreign_symbol!(_TEXT_BEGIN)..foreign_symbol!(_TEXT_END)
+}
+
+pub fn rodata_region() -> Range<usize> {
+    foreign_symbol!(_RODATA_BEGIN)..foreign_symbol!(_RODATA_END)
+}
+
+pub fn data_region() -> Range<usize> {
+    foreign_symbol!(_DATA_BEGIN)..foreign_symbol!(_DATA_END)
+}
+
+pub fn bss_region() -> Range<usize> {
+    foreign_symbol!(_BSS_BEGIN)..foreign_symbol!(_BSS_END)
+}
+
+pub fn got_region() -> Range<usize> {
+    foreign_symbol!(_GOT_BEGIN)..foreign_symbol!(_GOT_END)
+}
+
+pub fn stack_guard_region() -> Range<usize> {
+    foreign_symbol!(_STACK_GUARD_PAGE)..foreign_symbol!(_STACK_BOTTOM)
+}
+
+pub fn boot_stack_region() -> Range<usize> {
+    foreign_symbol!(_STACK_BOTTOM)..foreign_symbol!(_STACK_TOP)
+}
@@ -20,10 +20,133 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use x86_64::structures::idt::InterruptStackFrame;
+use alloc::vec::Vec;
+use core::fmt;
 
+use spin::RwLock;
+use x86_64::instructions;
+use x86_64::registers::control::Cr2;
+use x86_64::registers::rflags::RFlags;
+use x86_64::structures::idt::{DescriptorTable, InterruptStackFrame, PageFaultErrorCode, SelectorErrorCode};
+use x86_64::VirtAddr;
+
+use super::trap::TrapFrame;
+use super::watchpoint::{self, WatchKind};
+use super::{backtrace, elf, gdb, paging};
+use crate::kernel::panic;
+use crate::kernel::recovery::{self, Event};
 use crate::serial_println;
 
+/// Lets the code that expects the given exception resume, instead of the handler halting the kernel. Otherwise,
+/// hands the state of the faulting code, including its registers, over to the panic that follows.
+fn report_fatal(frame: &TrapFrame) {
+    recovery::resume(Event::Exception { vector: frame.vector as u8, error_code: frame.error_code() });
+
+    panic::set_cpu_state(frame.cpu_state());
+}
+
+/// Human-readable decoding of the selector error code pushed by #TS, #NP, #SS and #GP.
+struct SelectorCause(u64);
+
+impl fmt::Display for SelectorCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let selector = SelectorErrorCode::new_truncate(self.0);
+        if selector.is_null() {
+            return write!(f, "no selector");
+        }
+
+        let table = match selector.descriptor_table() {
+            DescriptorTable::Gdt => "GDT",
+            DescriptorTable::Idt => "IDT",
+            DescriptorTable::Ldt => "LDT",
+        };
+
+        write!(f, "index={} table={} external={}", selector.index(), table, selector.external() as u8)
+    }
+}
+
+/// Division Error Exception (#DE, 0x00)
+///
+/// A division error exception occurs when dividing any number by zero using the `DIV` or `IDIV` instruction, or
+/// when the quotient is too large to fit into the destination operand.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/Exceptions#Division_Error
+pub struct DivisionErrorException;
+
+impl DivisionErrorException {
+    pub const CODE: u8 = 0x00;
+    pub const MNEMONIC: &'static str = "#DE";
+
+    pub fn handler(frame: &TrapFrame) {
+        report_fatal(frame);
+        panic!("({}, {:#04X}) @ {:#?}", Self::MNEMONIC, Self::CODE, frame.stack_frame());
+    }
+}
+
+/// Debug Exception (#DB, 0x01)
+///
+/// A debug exception occurs on a number of debug events, such as single-stepping with RFLAGS.TF, hardware
+/// breakpoints and watchpoints configured through the debug registers, or task switches into a task with the T flag
+/// set. Whether it is a fault or a trap depends on the condition, which is reported in DR6.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/Exceptions#Debug
+pub struct DebugException;
+
+impl DebugException {
+    pub const CODE: u8 = 0x01;
+    pub const MNEMONIC: &'static str = "#DB";
+
+    /// Called by the entry stub in `trap.rs`, which saves every register so that GDB can inspect them.
+    pub fn handler(frame: &mut TrapFrame) {
+        let status = watchpoint::take_status();
+        let fired = status.triggered().find_map(|slot| Some((slot, watchpoint::get(slot)?)));
+
+        // Execute watchpoints are faults, which would fire again as soon as the instruction is resumed.
+        if fired.is_some_and(|(_, watchpoint)| watchpoint.kind == WatchKind::Execute) {
+            frame.rflags |= RFlags::RESUME_FLAG.bits();
+        }
+
+        if gdb::is_enabled() {
+            return gdb::enter(frame, fired.map(|(_, watchpoint)| watchpoint));
+        }
+
+        recovery::record(Event::Exception { vector: Self::CODE, error_code: None });
+
+        serial_println!("({}, {:#04X}) @\n{}", Self::MNEMONIC, Self::CODE, frame.cpu_state());
+        for slot in status.triggered() {
+            match watchpoint::get(slot) {
+                Some(watchpoint) => serial_println!(
+                    "watchpoint {} fired: {:?} of {} bytes at {:#X}",
+                    slot, watchpoint.kind, watchpoint.len, watchpoint.addr.as_u64(),
+                ),
+                None => serial_println!("watchpoint {} fired, but is not set", slot),
+            }
+        }
+        if status.is_single_step() {
+            serial_println!("single step");
+        }
+        backtrace::print_from(frame.registers.rbp);
+    }
+}
+
+/// Non-Maskable Interrupt (NMI, 0x02)
+///
+/// A non-maskable interrupt is raised by the hardware to signal conditions that cannot be ignored, such as memory
+/// parity errors or watchdog timers. Unlike other interrupts, it is not affected by the interrupt flag.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/Non_Maskable_Interrupt
+pub struct NonMaskableInterrupt;
+
+impl NonMaskableInterrupt {
+    pub const CODE: u8 = 0x02;
+    pub const MNEMONIC: &'static str = "NMI";
+
+    pub extern "x86-interrupt" fn handler(stack_frame: InterruptStackFrame) {
+        recovery::record(Event::Exception { vector: Self::CODE, error_code: None });
+        serial_println!("({}, {:#04X}) @ {:#?}", Self::MNEMONIC, Self::CODE, stack_frame);
+    }
+}
+
 /// Breakpoint Exception (#BP, 0x03)
 ///
 /// A breakpoint exception occurs when the processor encounters a debug breakpoint instruction in enabling the
@@ -36,8 +159,85 @@ impl BreakpointException {
     pub const CODE: u8 = 0x03;
     pub const MNEMONIC: &'static str = "#BP";
 
-    pub extern "x86-interrupt" fn handler(stack_frame: InterruptStackFrame) {
-        serial_println!("({}, {:#04X}) @ {:#?}", Self::MNEMONIC, Self::CODE, stack_frame);
+    /// Called by the entry stub in `trap.rs`, which saves every register so that GDB can inspect them.
+    pub fn handler(frame: &mut TrapFrame) {
+        if gdb::is_enabled() {
+            return gdb::enter(frame, None);
+        }
+
+        recovery::record(Event::Exception { vector: Self::CODE, error_code: None });
+        serial_println!("({}, {:#04X}) @\n{}", Self::MNEMONIC, Self::CODE, frame.cpu_state());
+    }
+}
+
+/// Overflow Exception (#OF, 0x04)
+///
+/// An overflow exception occurs when the `INTO` instruction is executed while the overflow bit in RFLAGS is set.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/Exceptions#Overflow
+pub struct OverflowException;
+
+impl OverflowException {
+    pub const CODE: u8 = 0x04;
+    pub const MNEMONIC: &'static str = "#OF";
+
+    pub fn handler(frame: &TrapFrame) {
+        report_fatal(frame);
+        panic!("({}, {:#04X}) @ {:#?}", Self::MNEMONIC, Self::CODE, frame.stack_frame());
+    }
+}
+
+/// Bound Range Exceeded Exception (#BR, 0x05)
+///
+/// A bound range exceeded exception occurs when the `BOUND` instruction is executed and the index is out of the
+/// bounds given by the operand.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/Exceptions#Bound_Range_Exceeded
+pub struct BoundRangeExceededException;
+
+impl BoundRangeExceededException {
+    pub const CODE: u8 = 0x05;
+    pub const MNEMONIC: &'static str = "#BR";
+
+    pub fn handler(frame: &TrapFrame) {
+        report_fatal(frame);
+        panic!("({}, {:#04X}) @ {:#?}", Self::MNEMONIC, Self::CODE, frame.stack_frame());
+    }
+}
+
+/// Invalid Opcode Exception (#UD, 0x06)
+///
+/// An invalid opcode exception occurs when the processor tries to execute an invalid or undefined opcode, or an
+/// instruction with invalid prefixes or operands.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/Exceptions#Invalid_Opcode
+pub struct InvalidOpcodeException;
+
+impl InvalidOpcodeException {
+    pub const CODE: u8 = 0x06;
+    pub const MNEMONIC: &'static str = "#UD";
+
+    pub fn handler(frame: &TrapFrame) {
+        report_fatal(frame);
+        panic!("({}, {:#04X}) @ {:#?}", Self::MNEMONIC, Self::CODE, frame.stack_frame());
+    }
+}
+
+/// Device Not Available Exception (#NM, 0x07)
+///
+/// A device not available exception occurs when an FPU instruction is attempted while there is no FPU or the FPU
+/// is disabled through the EM and TS bits of CR0.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/Exceptions#Device_Not_Available
+pub struct DeviceNotAvailableException;
+
+impl DeviceNotAvailableException {
+    pub const CODE: u8 = 0x07;
+    pub const MNEMONIC: &'static str = "#NM";
+
+    pub fn handler(frame: &TrapFrame) {
+        report_fatal(frame);
+        panic!("({}, {:#04X}) @ {:#?}", Self::MNEMONIC, Self::CODE, frame.stack_frame());
     }
 }
 
@@ -54,7 +254,446 @@ impl DoubleFaultException {
     pub const CODE: u8 = 0x08;
     pub const MNEMONIC: &'static str = "#DF";
 
-    pub extern "x86-interrupt" fn handler(stack_frame: InterruptStackFrame, err_code: u64) -> ! {
-        panic!("({}, {:#04X}) @ {:#?}, E={}", Self::MNEMONIC, Self::CODE, stack_frame, err_code);
+    /// Runs on its own stack, so that it also catches kernel stack overflows: the page fault on the guard page
+    /// cannot be delivered on the overflowed stack, which turns it into a double fault.
+    pub fn handler(frame: &TrapFrame) -> ! {
+        let err_code = frame.error_code;
+        let faulting_addr = Cr2::read();
+
+        report_fatal(frame);
+        if elf::stack_guard_region().contains(&(faulting_addr.as_u64() as usize)) {
+            panic!(
+                "({}, {:#04X}) kernel stack overflow: RSP={:#X}, CR2={:#X}",
+                Self::MNEMONIC, Self::CODE, frame.rsp, faulting_addr.as_u64()
+            );
+        }
+
+        panic!("({}, {:#04X}) @ {:#?}, E={}", Self::MNEMONIC, Self::CODE, frame.stack_frame(), err_code);
+    }
+}
+
+/// Invalid TSS Exception (#TS, 0x0A)
+///
+/// An invalid TSS exception occurs when an invalid segment selector is referenced as part of a task switch, or as
+/// a result of a control transfer through a gate descriptor.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/Exceptions#Invalid_TSS
+pub struct InvalidTSSException;
+
+impl InvalidTSSException {
+    pub const CODE: u8 = 0x0A;
+    pub const MNEMONIC: &'static str = "#TS";
+
+    pub fn handler(frame: &TrapFrame) {
+        let err_code = frame.error_code;
+        report_fatal(frame);
+        panic!(
+            "({}, {:#04X}) @ {:#?}, E=[{}]",
+            Self::MNEMONIC, Self::CODE, frame.stack_frame(), SelectorCause(err_code)
+        );
+    }
+}
+
+/// Segment Not Present Exception (#NP, 0x0B)
+///
+/// A segment not present exception occurs when trying to load a segment or gate which has its present bit cleared.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/Exceptions#Segment_Not_Present
+pub struct SegmentNotPresentException;
+
+impl SegmentNotPresentException {
+    pub const CODE: u8 = 0x0B;
+    pub const MNEMONIC: &'static str = "#NP";
+
+    pub fn handler(frame: &TrapFrame) {
+        let err_code = frame.error_code;
+        report_fatal(frame);
+        panic!(
+            "({}, {:#04X}) @ {:#?}, E=[{}]",
+            Self::MNEMONIC, Self::CODE, frame.stack_frame(), SelectorCause(err_code)
+        );
+    }
+}
+
+/// Stack-Segment Fault Exception (#SS, 0x0C)
+///
+/// A stack-segment fault exception occurs when loading a stack segment that is not present, when a stack related
+/// instruction references a non-canonical address, or when a stack limit check fails.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/Exceptions#Stack-Segment_Fault
+pub struct StackSegmentFaultException;
+
+impl StackSegmentFaultException {
+    pub const CODE: u8 = 0x0C;
+    pub const MNEMONIC: &'static str = "#SS";
+
+    pub fn handler(frame: &TrapFrame) {
+        let err_code = frame.error_code;
+        report_fatal(frame);
+        panic!(
+            "({}, {:#04X}) @ {:#?}, E=[{}]",
+            Self::MNEMONIC, Self::CODE, frame.stack_frame(), SelectorCause(err_code)
+        );
+    }
+}
+
+/// General Protection Fault Exception (#GP, 0x0D)
+///
+/// A general protection fault exception occurs for various reasons, most commonly segment errors, executing
+/// privileged instructions outside ring 0, writing reserved bits of control registers and accessing non-canonical
+/// addresses.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/Exceptions#General_Protection_Fault
+pub struct GeneralProtectionFaultException;
+
+impl GeneralProtectionFaultException {
+    pub const CODE: u8 = 0x0D;
+    pub const MNEMONIC: &'static str = "#GP";
+
+    pub fn handler(frame: &TrapFrame) {
+        let err_code = frame.error_code;
+        report_fatal(frame);
+        panic!(
+            "({}, {:#04X}) @ {:#?}, E=[{}]",
+            Self::MNEMONIC, Self::CODE, frame.stack_frame(), SelectorCause(err_code)
+        );
+    }
+}
+
+/// A hook that gets a chance to resolve a page fault, e.g. for demand paging or copy-on-write.
+///
+/// A resolver returns `true` if it has fixed the cause of the fault, in which case the faulting instruction is
+/// restarted.
+pub type PageFaultResolver = fn(VirtAddr, PageFaultErrorCode) -> bool;
+
+static PAGE_FAULT_RESOLVERS: RwLock<Vec<PageFaultResolver>> = RwLock::new(Vec::new());
+
+/// Human-readable decoding of the page fault error code.
+struct PageFaultCause(PageFaultErrorCode);
+
+impl fmt::Display for PageFaultCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bit = |flag: PageFaultErrorCode| self.0.contains(flag) as u8;
+
+        write!(
+            f,
+            "present={} write={} user={} reserved={} fetch={} pkey={}",
+            bit(PageFaultErrorCode::PROTECTION_VIOLATION),
+            bit(PageFaultErrorCode::CAUSED_BY_WRITE),
+            bit(PageFaultErrorCode::USER_MODE),
+            bit(PageFaultErrorCode::MALFORMED_TABLE),
+            bit(PageFaultErrorCode::INSTRUCTION_FETCH),
+            bit(PageFaultErrorCode::PROTECTION_KEY),
+        )
+    }
+}
+
+/// Page Fault Exception (#PF, 0x0E)
+///
+/// A page fault exception occurs when a page directory or table entry is not present in physical memory, when a
+/// protection check fails or when a reserved bit is set. The faulting virtual address is stored in the CR2 register.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/Exceptions#Page_Fault
+pub struct PageFaultException;
+
+impl PageFaultException {
+    pub const CODE: u8 = 0x0E;
+    pub const MNEMONIC: &'static str = "#PF";
+
+    /// Registers a hook that is consulted before a page fault is treated as fatal.
+    pub fn register_resolver(resolver: PageFaultResolver) {
+        instructions::interrupts::without_interrupts(|| PAGE_FAULT_RESOLVERS.write().push(resolver));
+    }
+
+    /// Removes a previously registered hook.
+    pub fn unregister_resolver(resolver: PageFaultResolver) {
+        instructions::interrupts::without_interrupts(
+            || PAGE_FAULT_RESOLVERS.write().retain(|&other| other as usize != resolver as usize)
+        );
+    }
+
+    fn resolve(faulting_addr: VirtAddr, err_code: PageFaultErrorCode) -> bool {
+        // The registry might be locked by the code that faulted, in which case nobody gets to resolve the fault.
+        match PAGE_FAULT_RESOLVERS.try_read() {
+            Some(resolvers) => resolvers.iter().any(|resolver| resolver(faulting_addr, err_code)),
+            None => false,
+        }
+    }
+
+    pub fn handler(frame: &TrapFrame) {
+        let faulting_addr = Cr2::read();
+        let err_code = PageFaultErrorCode::from_bits_truncate(frame.error_code);
+
+        if elf::stack_guard_region().contains(&(faulting_addr.as_u64() as usize)) {
+            report_fatal(frame);
+            panic!(
+                "({}, {:#04X}) kernel stack overflow: RSP={:#X}, CR2={:#X}",
+                Self::MNEMONIC, Self::CODE, frame.rsp, faulting_addr.as_u64()
+            );
+        }
+
+        if Self::resolve(faulting_addr, err_code) {
+            return;
+        }
+
+        report_fatal(frame);
+        serial_println!("{}", paging::walk(faulting_addr));
+
+        panic!(
+            "({}, {:#04X}) @ {:#?}, CR2={:#X}, E=[{}]",
+            Self::MNEMONIC, Self::CODE, frame.stack_frame(), faulting_addr.as_u64(), PageFaultCause(err_code)
+        );
+    }
+}
+
+/// x87 Floating-Point Exception (#MF, 0x10)
+///
+/// An x87 floating-point exception occurs when the `FWAIT` or `WAIT` instruction, or any waiting floating-point
+/// instruction is executed while an unmasked floating-point exception is pending.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/Exceptions#x87_Floating-Point_Exception
+pub struct X87FloatingPointException;
+
+impl X87FloatingPointException {
+    pub const CODE: u8 = 0x10;
+    pub const MNEMONIC: &'static str = "#MF";
+
+    pub fn handler(frame: &TrapFrame) {
+        report_fatal(frame);
+        panic!("({}, {:#04X}) @ {:#?}", Self::MNEMONIC, Self::CODE, frame.stack_frame());
+    }
+}
+
+/// Alignment Check Exception (#AC, 0x11)
+///
+/// An alignment check exception occurs when alignment checking is enabled and an unaligned memory data reference
+/// is performed. Alignment checking is only performed in ring 3.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/Exceptions#Alignment_Check
+pub struct AlignmentCheckException;
+
+impl AlignmentCheckException {
+    pub const CODE: u8 = 0x11;
+    pub const MNEMONIC: &'static str = "#AC";
+
+    pub fn handler(frame: &TrapFrame) {
+        let err_code = frame.error_code;
+        report_fatal(frame);
+        panic!("({}, {:#04X}) @ {:#?}, E={}", Self::MNEMONIC, Self::CODE, frame.stack_frame(), err_code);
+    }
+}
+
+/// Machine Check Exception (#MC, 0x12)
+///
+/// A machine check exception occurs when the processor detects internal errors, such as bad memory, bus errors or
+/// cache errors. The details are model specific and reported through the machine check MSRs.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/Exceptions#Machine_Check
+pub struct MachineCheckException;
+
+impl MachineCheckException {
+    pub const CODE: u8 = 0x12;
+    pub const MNEMONIC: &'static str = "#MC";
+
+    pub fn handler(frame: &TrapFrame) -> ! {
+        report_fatal(frame);
+        panic!("({}, {:#04X}) @ {:#?}", Self::MNEMONIC, Self::CODE, frame.stack_frame());
+    }
+}
+
+/// SIMD Floating-Point Exception (#XM, 0x13)
+///
+/// A SIMD floating-point exception occurs when an unmasked 128-bit media floating-point exception occurs and the
+/// OSXMMEXCPT bit of CR4 is set.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/Exceptions#SIMD_Floating-Point_Exception
+pub struct SIMDFloatingPointException;
+
+impl SIMDFloatingPointException {
+    pub const CODE: u8 = 0x13;
+    pub const MNEMONIC: &'static str = "#XM";
+
+    pub fn handler(frame: &TrapFrame) {
+        report_fatal(frame);
+        panic!("({}, {:#04X}) @ {:#?}", Self::MNEMONIC, Self::CODE, frame.stack_frame());
+    }
+}
+
+/// Virtualization Exception (#VE, 0x14)
+///
+/// A virtualization exception occurs when a guest violates an EPT restriction while the corresponding
+/// "EPT-violation #VE" VM-execution control is set.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/Exceptions#Virtualization_Exception
+pub struct VirtualizationException;
+
+impl VirtualizationException {
+    pub const CODE: u8 = 0x14;
+    pub const MNEMONIC: &'static str = "#VE";
+
+    pub fn handler(frame: &TrapFrame) {
+        report_fatal(frame);
+        panic!("({}, {:#04X}) @ {:#?}", Self::MNEMONIC, Self::CODE, frame.stack_frame());
+    }
+}
+
+/// Control Protection Exception (#CP, 0x15)
+///
+/// A control protection exception occurs when a control flow transfer violates the rules enforced by Control-flow
+/// Enforcement Technology (CET), e.g. when a return address does not match the one saved on the shadow stack.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/Exceptions#Control_Protection_Exception
+pub struct ControlProtectionException;
+
+impl ControlProtectionException {
+    pub const CODE: u8 = 0x15;
+    pub const MNEMONIC: &'static str = "#CP";
+
+    fn cause(err_code: u64) -> &'static str {
+        match err_code & 0x7FFF {
+            1 => "NEAR-RET",
+            2 => "FAR-RET/IRET",
+            3 => "ENDBRANCH",
+            4 => "RSTORSSP",
+            5 => "SETSSBSY",
+            _ => "unknown",
+        }
+    }
+
+    pub fn handler(frame: &TrapFrame) {
+        let err_code = frame.error_code;
+        report_fatal(frame);
+        panic!(
+            "({}, {:#04X}) @ {:#?}, E=[{} enclave={}]",
+            Self::MNEMONIC, Self::CODE, frame.stack_frame(), Self::cause(err_code), (err_code >> 15) & 1
+        );
+    }
+}
+
+/// Hypervisor Injection Exception (#HV, 0x1C)
+///
+/// A hypervisor injection exception is injected by a hypervisor into a secure guest to notify it of pending
+/// events, when AMD SEV-SNP restricted injection is enabled.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/Exceptions#Hypervisor_Injection_Exception
+pub struct HypervisorInjectionException;
+
+impl HypervisorInjectionException {
+    pub const CODE: u8 = 0x1C;
+    pub const MNEMONIC: &'static str = "#HV";
+
+    pub fn handler(frame: &TrapFrame) {
+        report_fatal(frame);
+        panic!("({}, {:#04X}) @ {:#?}", Self::MNEMONIC, Self::CODE, frame.stack_frame());
+    }
+}
+
+/// VMM Communication Exception (#VC, 0x1D)
+///
+/// A VMM communication exception occurs in an SEV-ES guest when an event that requires the hypervisor occurs. The
+/// error code holds the `#VMEXIT` code of the event.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/Exceptions#VMM_Communication_Exception
+pub struct VMMCommunicationException;
+
+impl VMMCommunicationException {
+    pub const CODE: u8 = 0x1D;
+    pub const MNEMONIC: &'static str = "#VC";
+
+    pub fn handler(frame: &TrapFrame) {
+        let err_code = frame.error_code;
+        report_fatal(frame);
+        panic!("({}, {:#04X}) @ {:#?}, E={:#X}", Self::MNEMONIC, Self::CODE, frame.stack_frame(), err_code);
+    }
+}
+
+/// Security Exception (#SX, 0x1E)
+///
+/// A security exception signals security-sensitive events to the VMM, such as the redirection of an external INIT
+/// which is indicated by an error code of 1.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/Exceptions#Security_Exception
+pub struct SecurityException;
+
+impl SecurityException {
+    pub const CODE: u8 = 0x1E;
+    pub const MNEMONIC: &'static str = "#SX";
+
+    pub fn handler(frame: &TrapFrame) {
+        let err_code = frame.error_code;
+        report_fatal(frame);
+        panic!("({}, {:#04X}) @ {:#?}, E={}", Self::MNEMONIC, Self::CODE, frame.stack_frame(), err_code);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::arch::asm;
+    use core::ptr;
+
+    use x86_64::structures::paging::PageTableFlags;
+
+    use super::*;
+    use super::super::heap;
+    use crate::testing;
+
+    #[test_case]
+    fn breakpoint_returns() {
+        assert_eq!(recovery::catch(|| unsafe { asm!("int3") }), None);
+
+        let event = Event::Exception { vector: BreakpointException::CODE, error_code: None };
+        assert_eq!(recovery::take_event(), Some(event));
+    }
+
+    #[test_case]
+    fn write_to_unmapped_page() {
+        let error_code = testing::expect_exception(PageFaultException::CODE, || unsafe {
+            ptr::write_volatile(0x1000 as *mut u8, 0);
+        });
+
+        let error_code = PageFaultErrorCode::from_bits_truncate(error_code.unwrap());
+        assert!(error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE));
+        assert!(!error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION));
+    }
+
+    #[test_case]
+    fn invalid_opcode() {
+        testing::expect_exception(InvalidOpcodeException::CODE, || unsafe { asm!("ud2") });
+    }
+
+    /// Two pages right below the heap, which nothing else maps.
+    const LAZY_PAGE: u64 = heap::HEAP_START as u64 - 0x10000;
+    const NESTED_PAGE: u64 = LAZY_PAGE + 0x1000;
+
+    /// Maps both pages on demand, and touches the second one while it resolves a fault on the first.
+    fn resolve_nested(faulting_addr: VirtAddr, _err_code: PageFaultErrorCode) -> bool {
+        let page = faulting_addr.align_down(0x1000u64);
+        if page.as_u64() != LAZY_PAGE && page.as_u64() != NESTED_PAGE {
+            return false;
+        }
+
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        paging::map_region(page..page + 1u64, flags).expect("failed to map a lazy page");
+
+        if page.as_u64() == LAZY_PAGE {
+            unsafe { ptr::write_volatile(NESTED_PAGE as *mut u8, 0x5A); }
+        }
+        true
+    }
+
+    #[test_case]
+    fn resolver_faults_while_running() {
+        PageFaultException::register_resolver(resolve_nested);
+        let event = recovery::catch(|| unsafe { ptr::write_volatile(LAZY_PAGE as *mut u8, 0xA5) });
+        PageFaultException::unregister_resolver(resolve_nested);
+
+        assert_eq!(event, None);
+        assert_eq!(unsafe { ptr::read_volatile(LAZY_PAGE as *const u8) }, 0xA5);
+        assert_eq!(unsafe { ptr::read_volatile(NESTED_PAGE as *const u8) }, 0x5A);
+
+        unsafe {
+            paging::unmap_region(VirtAddr::new(LAZY_PAGE)..VirtAddr::new(NESTED_PAGE + 0x1000))
+                .expect("failed to unmap the lazy pages");
+        }
     }
 }
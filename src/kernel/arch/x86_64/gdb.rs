@@ -0,0 +1,531 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::Mutex;
+use x86_64::registers::control::{Cr0, Cr0Flags};
+use x86_64::registers::rflags::RFlags;
+use x86_64::registers::segmentation::{Segment, DS, ES, FS, GS};
+use x86_64::VirtAddr;
+
+use super::paging;
+use super::serial::{self, Role};
+use super::trap::TrapFrame;
+use super::watchpoint::{self, WatchKind, Watchpoint};
+
+/// The largest packet that the stub accepts, which is announced to GDB in hexadecimal.
+const MAX_PACKET_SIZE: usize = 0x400;
+const MAX_BREAKPOINTS: usize = 32;
+
+const INT3: u8 = 0xCC;
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// The number of registers in the `g` packet: the general purpose registers, RIP, EFLAGS and the segment registers.
+const REGISTER_COUNT: usize = 24;
+const RIP: usize = 16;
+const EFLAGS: usize = 17;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static CONNECTED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GdbError {
+    NoDebuggerPort,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Breakpoint {
+    addr: u64,
+    original: u8,
+}
+
+static BREAKPOINTS: Mutex<[Option<Breakpoint>; MAX_BREAKPOINTS]> = Mutex::new([None; MAX_BREAKPOINTS]);
+
+/// Outgoing Packet
+///
+/// A reply that is sent to GDB while it is built, as `$<payload>#<checksum>`.
+struct Packet {
+    checksum: u8,
+}
+
+impl Packet {
+    fn start() -> Self {
+        serial::write_bytes(Role::Debugger, b"$");
+
+        Packet { checksum: 0 }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.checksum = bytes.iter().fold(self.checksum, |checksum, &byte| checksum.wrapping_add(byte));
+        serial::write_bytes(Role::Debugger, bytes);
+    }
+
+    fn push_hex(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.push(&[HEX_DIGITS[(byte >> 4) as usize], HEX_DIGITS[(byte & 0xF) as usize]]);
+        }
+    }
+
+    fn finish(self) {
+        let checksum = [HEX_DIGITS[(self.checksum >> 4) as usize], HEX_DIGITS[(self.checksum & 0xF) as usize]];
+        serial::write_bytes(Role::Debugger, &[b'#', checksum[0], checksum[1]]);
+    }
+}
+
+impl fmt::Write for Packet {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push(s.as_bytes());
+
+        Ok(())
+    }
+}
+
+fn reply(payload: &[u8]) {
+    let mut packet = Packet::start();
+    packet.push(payload);
+    packet.finish();
+}
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|digit| digit as u8)
+}
+
+fn parse_hex(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || digits.len() > 16 {
+        return None;
+    }
+
+    digits.iter().try_fold(0, |value, &digit| Some(value << 4 | hex_digit(digit)? as u64))
+}
+
+/// Decodes pairs of hex digits into bytes, in order.
+fn hex_bytes(digits: &[u8]) -> impl Iterator<Item = Option<u8>> + '_ {
+    digits.chunks(2).map(|pair| match pair {
+        [high, low] => Some(hex_digit(*high)? << 4 | hex_digit(*low)?),
+        _ => None,
+    })
+}
+
+fn split(bytes: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let position = bytes.iter().position(|&byte| byte == separator)?;
+
+    Some((&bytes[..position], &bytes[position + 1..]))
+}
+
+/// Waits for the next packet with a valid checksum, acknowledges it and returns the length of its payload.
+fn receive_packet(buffer: &mut [u8; MAX_PACKET_SIZE]) -> usize {
+    loop {
+        // Everything up to the start of a packet, e.g. the acknowledgements of our replies, is skipped.
+        while serial::poll_byte(Role::Debugger) != b'$' {}
+
+        let (mut length, mut checksum, mut overflow) = (0, 0u8, false);
+        loop {
+            match serial::poll_byte(Role::Debugger) {
+                b'#' => break,
+                byte if length < MAX_PACKET_SIZE => {
+                    buffer[length] = byte;
+                    length += 1;
+                    checksum = checksum.wrapping_add(byte);
+                }
+                _ => overflow = true,
+            }
+        }
+
+        let high = hex_digit(serial::poll_byte(Role::Debugger));
+        let low = hex_digit(serial::poll_byte(Role::Debugger));
+        if !overflow && high.zip(low).map(|(high, low)| high << 4 | low) == Some(checksum) {
+            serial::write_bytes(Role::Debugger, b"+");
+            return length;
+        }
+
+        serial::write_bytes(Role::Debugger, b"-");
+    }
+}
+
+fn is_mapped(addr: u64) -> bool {
+    VirtAddr::try_new(addr).is_ok_and(|addr| paging::walk(addr).is_mapped())
+}
+
+/// Writes a byte even if its page is read-only, e.g. to place a breakpoint in `.text`.
+unsafe fn poke(addr: u64, byte: u8) {
+    let cr0 = Cr0::read();
+
+    Cr0::write(cr0 - Cr0Flags::WRITE_PROTECT);
+    (addr as *mut u8).write_volatile(byte);
+    Cr0::write(cr0);
+}
+
+/// Returns the value and size in bytes of the register with the given GDB number.
+fn read_register(frame: &TrapFrame, index: usize) -> Option<(u64, usize)> {
+    let registers = &frame.registers;
+
+    let value = match index {
+        0 => registers.rax,
+        1 => registers.rbx,
+        2 => registers.rcx,
+        3 => registers.rdx,
+        4 => registers.rsi,
+        5 => registers.rdi,
+        6 => registers.rbp,
+        7 => frame.rsp,
+        8 => registers.r8,
+        9 => registers.r9,
+        10 => registers.r10,
+        11 => registers.r11,
+        12 => registers.r12,
+        13 => registers.r13,
+        14 => registers.r14,
+        15 => registers.r15,
+        RIP => frame.rip,
+        EFLAGS => return Some((frame.rflags, 4)),
+        18 => return Some((frame.cs, 4)),
+        19 => return Some((frame.ss, 4)),
+        20 => return Some((DS::get_reg().0 as u64, 4)),
+        21 => return Some((ES::get_reg().0 as u64, 4)),
+        22 => return Some((FS::get_reg().0 as u64, 4)),
+        23 => return Some((GS::get_reg().0 as u64, 4)),
+        _ => return None,
+    };
+
+    Some((value, 8))
+}
+
+/// Returns the register with the given GDB number, unless it cannot be changed through the stub.
+fn register_mut(frame: &mut TrapFrame, index: usize) -> Option<&mut u64> {
+    let registers = &mut frame.registers;
+
+    Some(match index {
+        0 => &mut registers.rax,
+        1 => &mut registers.rbx,
+        2 => &mut registers.rcx,
+        3 => &mut registers.rdx,
+        4 => &mut registers.rsi,
+        5 => &mut registers.rdi,
+        6 => &mut registers.rbp,
+        7 => &mut frame.rsp,
+        8 => &mut registers.r8,
+        9 => &mut registers.r9,
+        10 => &mut registers.r10,
+        11 => &mut registers.r11,
+        12 => &mut registers.r12,
+        13 => &mut registers.r13,
+        14 => &mut registers.r14,
+        15 => &mut registers.r15,
+        RIP => &mut frame.rip,
+        EFLAGS => &mut frame.rflags,
+        _ => return None,
+    })
+}
+
+/// Decodes a little-endian register value of the given size.
+fn parse_register(digits: &[u8], size: usize) -> Option<u64> {
+    if digits.len() != size * 2 {
+        return None;
+    }
+
+    hex_bytes(digits).enumerate().try_fold(0, |value, (index, byte)| Some(value | (byte? as u64) << (index * 8)))
+}
+
+fn read_registers(frame: &TrapFrame) {
+    let mut packet = Packet::start();
+    for index in 0..REGISTER_COUNT {
+        let (value, size) = read_register(frame, index).unwrap();
+        packet.push_hex(&value.to_le_bytes()[..size]);
+    }
+    packet.finish();
+}
+
+fn write_registers(frame: &mut TrapFrame, mut digits: &[u8]) -> bool {
+    for index in 0..REGISTER_COUNT {
+        let (_, size) = read_register(frame, index).unwrap();
+        if digits.len() < size * 2 {
+            break;
+        }
+
+        let Some(value) = parse_register(&digits[..size * 2], size) else {
+            return false;
+        };
+        if let Some(register) = register_mut(frame, index) {
+            *register = value;
+        }
+        digits = &digits[size * 2..];
+    }
+
+    true
+}
+
+fn read_memory(args: &[u8]) {
+    let Some((addr, length)) = split(args, b',').and_then(|(addr, length)| Some((parse_hex(addr)?, parse_hex(length)?)))
+    else {
+        return reply(b"E01");
+    };
+
+    // Only the readable prefix of the range is sent, which GDB accepts as a partial read.
+    let length = length.min(MAX_PACKET_SIZE as u64 / 2 - 4);
+    let readable = (0..length).take_while(|&offset| addr.checked_add(offset).is_some_and(is_mapped)).count();
+    if readable == 0 {
+        return reply(b"E14");
+    }
+
+    let mut packet = Packet::start();
+    for offset in 0..readable as u64 {
+        packet.push_hex(&[unsafe { ((addr + offset) as *const u8).read_volatile() }]);
+    }
+    packet.finish();
+}
+
+fn write_memory(args: &[u8]) {
+    let parsed = split(args, b':').and_then(|(range, data)| {
+        let (addr, length) = split(range, b',')?;
+        Some((parse_hex(addr)?, parse_hex(length)?, data))
+    });
+    let Some((addr, length, data)) = parsed.filter(|(_, length, data)| data.len() as u64 == length * 2) else {
+        return reply(b"E01");
+    };
+
+    if !(0..length).all(|offset| addr.checked_add(offset).is_some_and(is_mapped)) {
+        return reply(b"E14");
+    }
+    if hex_bytes(data).any(|byte| byte.is_none()) {
+        return reply(b"E01");
+    }
+
+    for (offset, byte) in hex_bytes(data).enumerate() {
+        unsafe { poke(addr + offset as u64, byte.unwrap()); }
+    }
+
+    reply(b"OK");
+}
+
+fn insert_breakpoint(addr: u64) -> bool {
+    let mut breakpoints = BREAKPOINTS.lock();
+    if breakpoints.iter().flatten().any(|breakpoint| breakpoint.addr == addr) {
+        return true;
+    }
+
+    let Some(slot) = breakpoints.iter_mut().find(|slot| slot.is_none()) else {
+        return false;
+    };
+    if !is_mapped(addr) {
+        return false;
+    }
+
+    let original = unsafe { (addr as *const u8).read_volatile() };
+    unsafe { poke(addr, INT3); }
+    *slot = Some(Breakpoint { addr, original });
+
+    true
+}
+
+fn remove_breakpoint(addr: u64) -> bool {
+    let mut breakpoints = BREAKPOINTS.lock();
+    let Some(slot) = breakpoints.iter_mut().find(|slot| slot.is_some_and(|breakpoint| breakpoint.addr == addr))
+    else {
+        return false;
+    };
+
+    unsafe { poke(addr, slot.unwrap().original); }
+    *slot = None;
+
+    true
+}
+
+fn insert_watchpoint(addr: u64, kind: WatchKind, len: u64) -> bool {
+    VirtAddr::try_new(addr).is_ok_and(|addr| watchpoint::set(addr, kind, len as usize).is_ok())
+}
+
+fn remove_watchpoint(addr: u64, kind: WatchKind) -> bool {
+    VirtAddr::try_new(addr).is_ok_and(|addr| watchpoint::clear_address(addr, kind).is_ok())
+}
+
+/// Handles `Z` and `z` for software breakpoints (type 0), hardware breakpoints (type 1), write watchpoints (type 2)
+/// and access watchpoints (type 4); the debug registers cannot watch reads alone (type 3).
+fn breakpoint(args: &[u8], insert: bool) {
+    let mut fields = args.split(|&byte| byte == b',');
+    let (Some(kind), Some(addr), Some(len)) = (fields.next(), fields.next().and_then(parse_hex), fields.next()) else {
+        return reply(b"E01");
+    };
+
+    let watch_kind = match kind {
+        b"0" => None,
+        b"1" => Some(WatchKind::Execute),
+        b"2" => Some(WatchKind::Write),
+        b"4" => Some(WatchKind::ReadWrite),
+        _ => return reply(b""),
+    };
+
+    let done = match (watch_kind, insert) {
+        (None, true) => insert_breakpoint(addr),
+        (None, false) => remove_breakpoint(addr),
+        // The length of a hardware breakpoint is the kind of breakpoint instruction it stands for.
+        (Some(WatchKind::Execute), true) => insert_watchpoint(addr, WatchKind::Execute, 1),
+        (Some(kind), true) => parse_hex(len).is_some_and(|len| insert_watchpoint(addr, kind, len)),
+        (Some(kind), false) => remove_watchpoint(addr, kind),
+    };
+    reply(if done { b"OK" } else { b"E0E" });
+}
+
+/// Tells GDB why the kernel stopped: always because of SIGTRAP, and for data watchpoints also which address.
+fn stop_reply(watchpoint: Option<&Watchpoint>) {
+    let mut packet = Packet::start();
+    match watchpoint {
+        Some(watchpoint) if watchpoint.kind == WatchKind::Write => {
+            write!(packet, "T05watch:{:x};", watchpoint.addr.as_u64()).unwrap();
+        }
+        Some(watchpoint) if watchpoint.kind == WatchKind::ReadWrite => {
+            write!(packet, "T05awatch:{:x};", watchpoint.addr.as_u64()).unwrap();
+        }
+        _ => packet.push(b"S05"),
+    }
+    packet.finish();
+}
+
+fn query(args: &[u8]) {
+    if args.starts_with(b"Supported") {
+        let mut packet = Packet::start();
+        write!(packet, "PacketSize={:x}", MAX_PACKET_SIZE).unwrap();
+        packet.finish();
+    } else if args == b"Attached" {
+        reply(b"1");
+    } else {
+        reply(b"");
+    }
+}
+
+/// Lets the interrupted code run again, optionally from another address, and stepping a single instruction.
+fn resume(frame: &mut TrapFrame, args: &[u8], step: bool) {
+    if let Some(addr) = parse_hex(args) {
+        frame.rip = addr;
+    }
+
+    if step {
+        frame.rflags |= RFlags::TRAP_FLAG.bits();
+    }
+}
+
+/// Removes every breakpoint and stops reporting traps to GDB.
+fn detach() {
+    let breakpoints = *BREAKPOINTS.lock();
+    for breakpoint in breakpoints.iter().flatten() {
+        remove_breakpoint(breakpoint.addr);
+    }
+
+    CONNECTED.store(false, Ordering::SeqCst);
+    ENABLED.store(false, Ordering::SeqCst);
+}
+
+/// Reports the trap to GDB and serves its requests until it lets the interrupted code run again.
+///
+/// Interrupts are disabled while the stub runs, so the debugger port is polled.
+pub fn enter(frame: &mut TrapFrame, watchpoint: Option<Watchpoint>) {
+    // A single step ends with the trap that it raised.
+    frame.rflags &= !RFlags::TRAP_FLAG.bits();
+
+    // Until GDB has sent its first packet, nobody is listening for the stop reply.
+    if CONNECTED.load(Ordering::SeqCst) {
+        stop_reply(watchpoint.as_ref());
+    }
+
+    let mut buffer = [0; MAX_PACKET_SIZE];
+    loop {
+        let length = receive_packet(&mut buffer);
+        CONNECTED.store(true, Ordering::SeqCst);
+
+        let Some((&command, args)) = buffer[..length].split_first() else {
+            reply(b"");
+            continue;
+        };
+
+        match command {
+            b'?' => stop_reply(watchpoint.as_ref()),
+            b'g' => read_registers(frame),
+            b'G' => reply(if write_registers(frame, args) { b"OK" } else { b"E01" }),
+            b'p' => match parse_hex(args).and_then(|index| read_register(frame, index as usize)) {
+                Some((value, size)) => {
+                    let mut packet = Packet::start();
+                    packet.push_hex(&value.to_le_bytes()[..size]);
+                    packet.finish();
+                }
+                None => reply(b"E01"),
+            },
+            b'P' => {
+                let written = split(args, b'=').and_then(|(index, digits)| {
+                    let index = parse_hex(index)? as usize;
+                    let (_, size) = read_register(frame, index)?;
+                    let value = parse_register(digits, size)?;
+                    *register_mut(frame, index)? = value;
+                    Some(())
+                });
+                reply(if written.is_some() { b"OK" } else { b"E01" });
+            }
+            b'm' => read_memory(args),
+            b'M' => write_memory(args),
+            b'Z' => breakpoint(args, true),
+            b'z' => breakpoint(args, false),
+            b'q' => query(args),
+            b'H' => reply(b"OK"),
+            b'c' => return resume(frame, args, false),
+            b's' => return resume(frame, args, true),
+            b'D' => {
+                reply(b"OK");
+                return detach();
+            }
+            b'k' => return detach(),
+            _ => reply(b""),
+        }
+    }
+}
+
+/// Makes breakpoint and debug exceptions stop in the stub, which waits for GDB on the port of the debugger role.
+pub fn enable() -> Result<(), GdbError> {
+    if !serial::is_present(serial::port_of(Role::Debugger)) {
+        return Err(GdbError::NoDebuggerPort);
+    }
+
+    ENABLED.store(true, Ordering::SeqCst);
+
+    Ok(())
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn hex_is_parsed() {
+        assert_eq!(parse_hex(b"ffffffff80000000"), Some(0xFFFF_FFFF_8000_0000));
+        assert_eq!(parse_hex(b""), None);
+        assert_eq!(parse_hex(b"12g"), None);
+    }
+
+    #[test_case]
+    fn registers_are_little_endian() {
+        assert_eq!(parse_register(b"78563412", 4), Some(0x1234_5678));
+        assert_eq!(parse_register(b"7856", 4), None);
+    }
+}
@@ -20,6 +20,9 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use core::ops::Range;
+use core::ptr::addr_of;
+
 use lazy_static::lazy_static;
 use x86_64::instructions;
 use x86_64::instructions::segmentation::{CS, DS, ES, FS, GS, SS};
@@ -46,9 +49,8 @@ lazy_static! {
 
         tss.interrupt_stack_table[DoubleFaultException::IST_INDEX] = {
             static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-            let stack_bottom = VirtAddr::from_ptr(unsafe { &STACK });
-            let stack_top = stack_bottom + STACK_SIZE;
-            stack_top
+            let stack_bottom = VirtAddr::from_ptr(addr_of!(STACK));
+            stack_bottom + STACK_SIZE
         };
 
         tss
@@ -113,3 +115,24 @@ pub fn init() -> Result<(), ()> {
 
     Ok(())
 }
+
+/// Returns the stack that the processor switches to for the given IST index.
+pub fn interrupt_stack(index: usize) -> Range<VirtAddr> {
+    let stack_top = TSS.interrupt_stack_table[index];
+
+    stack_top - STACK_SIZE..stack_top
+}
+
+/// Returns the region of the GDT, e.g. to watch it for corruption.
+pub fn gdt_region() -> Range<VirtAddr> {
+    let start = VirtAddr::from_ptr(&GDT.0);
+
+    start..start + core::mem::size_of::<GlobalDescriptorTable>()
+}
+
+/// Returns the region of the TSS, e.g. to watch it for corruption.
+pub fn tss_region() -> Range<VirtAddr> {
+    let start = VirtAddr::from_ptr(&*TSS);
+
+    start..start + core::mem::size_of::<TaskStateSegment>()
+}
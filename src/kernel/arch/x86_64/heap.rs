@@ -0,0 +1,85 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use core::alloc::Layout;
+
+use linked_list_allocator::LockedHeap;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+use super::paging;
+use crate::serial_println;
+
+/// The kernel heap lives in the last gigabyte of the virtual address space, right above the kernel image.
+pub const HEAP_START: usize = 0xFFFF_FFFF_C000_0000;
+pub const HEAP_SIZE: usize = 1 << 20;
+
+#[global_allocator]
+static ALLOCATOR: LockedHeap = LockedHeap::empty();
+
+pub fn init() -> Result<(), ()> {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let heap_region = VirtAddr::new(HEAP_START as u64)..VirtAddr::new((HEAP_START + HEAP_SIZE) as u64);
+
+    // Back every page of the heap region with a physical frame.
+    paging::map_region(heap_region, flags).map_err(|_| ())?;
+
+    unsafe {
+        ALLOCATOR.lock().init(HEAP_START as *mut u8, HEAP_SIZE);
+    }
+
+    log::info!("heap: {} KiB at {:#X}", HEAP_SIZE / 1024, HEAP_START);
+
+    Ok(())
+}
+
+#[alloc_error_handler]
+fn on_alloc_error(layout: Layout) -> ! {
+    serial_println!("kernel heap exhausted: failed to allocate {} bytes aligned to {}", layout.size(), layout.align());
+
+    super::hlt_loop();
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    #[test_case]
+    fn freed_memory_is_reused() {
+        // Allocating twice as much as the heap holds in total only succeeds if every block is freed again.
+        const BLOCK_SIZE: usize = 4096;
+
+        for value in 0..2 * HEAP_SIZE / BLOCK_SIZE {
+            let boxed = Box::new([value as u8; BLOCK_SIZE]);
+            assert_eq!(boxed[BLOCK_SIZE - 1], value as u8);
+        }
+    }
+
+    #[test_case]
+    fn large_vector() {
+        let values: Vec<usize> = (0..HEAP_SIZE / 64).collect();
+        assert_eq!(values.iter().sum::<usize>(), (HEAP_SIZE / 64 - 1) * (HEAP_SIZE / 64) / 2);
+    }
+}
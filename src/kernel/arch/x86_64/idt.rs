@@ -23,7 +23,10 @@
 use lazy_static::lazy_static;
 use x86_64::structures::idt::InterruptDescriptorTable;
 
-use super::exceptions::{BreakpointException, DoubleFaultException};
+use super::apic;
+use super::exceptions::*;
+use super::irq;
+use super::trap;
 
 lazy_static! {
     /// Interrupt Descriptor Table (IDT)
@@ -38,15 +41,53 @@ lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
 
-        // Set breakpoint handler.
-        idt.breakpoint.set_handler_fn(BreakpointException::handler);
-
-        // Set double fault handler and a dedicated stack index for it.
+        // Exceptions go through entry stubs that save every register, so that the GDB stub can inspect them and a
+        // panic can report the state of the faulting code.
         unsafe {
-            idt.double_fault.set_handler_fn(DoubleFaultException::handler)
+            idt.divide_error.set_handler_addr(trap::entry(DivisionErrorException::CODE));
+            idt.debug.set_handler_addr(trap::entry(DebugException::CODE));
+            idt.breakpoint.set_handler_addr(trap::entry(BreakpointException::CODE));
+            idt.overflow.set_handler_addr(trap::entry(OverflowException::CODE));
+            idt.bound_range_exceeded.set_handler_addr(trap::entry(BoundRangeExceededException::CODE));
+            idt.invalid_opcode.set_handler_addr(trap::entry(InvalidOpcodeException::CODE));
+            idt.device_not_available.set_handler_addr(trap::entry(DeviceNotAvailableException::CODE));
+
+            // Set double fault handler and a dedicated stack index for it.
+            idt.double_fault.set_handler_addr(trap::entry(DoubleFaultException::CODE))
                             .set_stack_index(DoubleFaultException::IST_INDEX as u16);
+
+            idt.invalid_tss.set_handler_addr(trap::entry(InvalidTSSException::CODE));
+            idt.segment_not_present.set_handler_addr(trap::entry(SegmentNotPresentException::CODE));
+            idt.stack_segment_fault.set_handler_addr(trap::entry(StackSegmentFaultException::CODE));
+            idt.general_protection_fault.set_handler_addr(trap::entry(GeneralProtectionFaultException::CODE));
+
+            // Page faults run on the stack of the faulting code, since resolvers may fault again while they run. A
+            // fault on the guard page of an overflowed stack becomes a double fault instead.
+            idt.page_fault.set_handler_addr(trap::entry(PageFaultException::CODE));
+
+            idt.x87_floating_point.set_handler_addr(trap::entry(X87FloatingPointException::CODE));
+            idt.alignment_check.set_handler_addr(trap::entry(AlignmentCheckException::CODE));
+            idt.machine_check.set_handler_addr(trap::entry(MachineCheckException::CODE));
+            idt.simd_floating_point.set_handler_addr(trap::entry(SIMDFloatingPointException::CODE));
+            idt.virtualization.set_handler_addr(trap::entry(VirtualizationException::CODE));
+            idt.cp_protection_exception.set_handler_addr(trap::entry(ControlProtectionException::CODE));
+            idt.hv_injection_exception.set_handler_addr(trap::entry(HypervisorInjectionException::CODE));
+            idt.vmm_communication_exception.set_handler_addr(trap::entry(VMMCommunicationException::CODE));
+            idt.security_exception.set_handler_addr(trap::entry(SecurityException::CODE));
         }
 
+        // NMIs can arrive in the middle of another entry stub, so they keep a plain handler.
+        idt.non_maskable_interrupt.set_handler_fn(NonMaskableInterrupt::handler);
+
+        // Route the remaining vectors through the IRQ registry, so that drivers can install handlers at runtime.
+        for (index, entry) in irq::ENTRIES.iter().enumerate() {
+            idt[irq::VECTOR_BASE as usize + index].set_handler_fn(*entry);
+        }
+
+        // The local APIC acknowledges its own interrupts, so they bypass the IRQ registry.
+        idt[apic::SPURIOUS_VECTOR as usize].set_handler_fn(apic::on_spurious);
+        idt[apic::ERROR_VECTOR as usize].set_handler_fn(apic::on_error);
+
         idt
     };
 }
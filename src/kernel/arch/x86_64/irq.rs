@@ -0,0 +1,244 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use lazy_static::lazy_static;
+use spin::RwLock;
+use x86_64::instructions;
+use x86_64::structures::idt::{HandlerFunc, InterruptStackFrame};
+
+use super::apic;
+
+/// The first vector that is not reserved for CPU exceptions.
+pub const VECTOR_BASE: u8 = 0x20;
+pub const VECTOR_COUNT: usize = 256 - VECTOR_BASE as usize;
+
+/// The number of ISA interrupt lines, which are delivered on the vectors right after `VECTOR_BASE`.
+pub const LEGACY_IRQ_COUNT: u8 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    InvalidVector,
+    NotRegistered,
+}
+
+/// Identifies a registered handler so that it can be unregistered later on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrqHandle {
+    vector: u8,
+    id: u64,
+}
+
+impl IrqHandle {
+    pub fn vector(&self) -> u8 { self.vector }
+}
+
+type Handler = Box<dyn Fn(&InterruptStackFrame) + Send + Sync>;
+
+struct Action {
+    id: u64,
+    handler: Handler,
+}
+
+/// Interrupt Controller
+///
+/// The device that delivers external interrupts to the processor. It has to be told when an interrupt has been
+/// serviced, and may report interrupts that were never actually raised by a device.
+pub trait InterruptController: Sync {
+    /// Returns `true` if the interrupt on the given vector is spurious and must neither be handled nor acknowledged.
+    fn is_spurious(&self, vector: u8) -> bool;
+
+    /// Signals the end of the interrupt on the given vector.
+    fn end_of_interrupt(&self, vector: u8);
+
+    /// Stops or allows the given ISA interrupt line from raising interrupts.
+    fn set_masked(&self, line: u8, masked: bool);
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+static CONTROLLER: RwLock<Option<&'static dyn InterruptController>> = RwLock::new(None);
+
+lazy_static! {
+    /// IRQ Registry
+    ///
+    /// Holds the chain of handlers for every vector from 32 to 255. Several handlers may share a vector, in which
+    /// case all of them are invoked in the order they were registered, since only the drivers themselves can tell
+    /// whether their device raised the interrupt.
+    static ref ACTIONS: [RwLock<Vec<Action>>; VECTOR_COUNT] = core::array::from_fn(|_| RwLock::new(Vec::new()));
+}
+
+/// The entry stub of a single vector, which forwards to the common dispatcher.
+extern "x86-interrupt" fn entry<const VECTOR: u8>(stack_frame: InterruptStackFrame) {
+    dispatch(VECTOR, &stack_frame);
+}
+
+macro_rules! entries {
+    ($($row:literal),*) => ([$(
+        entry::<{ $row * 16 + 0x0 }>, entry::<{ $row * 16 + 0x1 }>, entry::<{ $row * 16 + 0x2 }>,
+        entry::<{ $row * 16 + 0x3 }>, entry::<{ $row * 16 + 0x4 }>, entry::<{ $row * 16 + 0x5 }>,
+        entry::<{ $row * 16 + 0x6 }>, entry::<{ $row * 16 + 0x7 }>, entry::<{ $row * 16 + 0x8 }>,
+        entry::<{ $row * 16 + 0x9 }>, entry::<{ $row * 16 + 0xA }>, entry::<{ $row * 16 + 0xB }>,
+        entry::<{ $row * 16 + 0xC }>, entry::<{ $row * 16 + 0xD }>, entry::<{ $row * 16 + 0xE }>,
+        entry::<{ $row * 16 + 0xF }>,
+    )*]);
+}
+
+/// Entry stubs for the vectors 32 to 255, in that order.
+pub static ENTRIES: [HandlerFunc; VECTOR_COUNT] = entries!(
+    0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0x8, 0x9, 0xA, 0xB, 0xC, 0xD, 0xE, 0xF
+);
+
+fn dispatch(vector: u8, stack_frame: &InterruptStackFrame) {
+    let controller = *CONTROLLER.read();
+
+    if controller.is_some_and(|controller| controller.is_spurious(vector)) {
+        return;
+    }
+
+    let actions = ACTIONS[(vector - VECTOR_BASE) as usize].read();
+
+    if actions.is_empty() {
+        log::warn!("irq: no handler for vector {:#04X}", vector);
+    }
+
+    for action in actions.iter() {
+        (action.handler)(stack_frame);
+    }
+
+    if let Some(controller) = controller {
+        controller.end_of_interrupt(vector);
+    }
+}
+
+/// Sets the interrupt controller that is acknowledged once the handlers of an interrupt have run.
+pub fn set_controller(controller: &'static dyn InterruptController) {
+    instructions::interrupts::without_interrupts(|| *CONTROLLER.write() = Some(controller));
+}
+
+/// Returns the vector that the given ISA interrupt line is delivered on.
+pub fn legacy_vector(line: u8) -> u8 {
+    VECTOR_BASE + line
+}
+
+/// Stops the given ISA interrupt line from raising interrupts.
+pub fn mask(line: u8) {
+    if let Some(controller) = *CONTROLLER.read() {
+        controller.set_masked(line, true);
+    }
+}
+
+/// Allows the given ISA interrupt line to raise interrupts.
+pub fn unmask(line: u8) {
+    if let Some(controller) = *CONTROLLER.read() {
+        controller.set_masked(line, false);
+    }
+}
+
+/// Returns `true` if the IDT routes the given vector through the registry, i.e. it is neither a CPU exception nor
+/// one of the vectors that the local APIC handles on its own.
+fn is_dispatched(vector: u8) -> bool {
+    vector >= VECTOR_BASE && vector != apic::SPURIOUS_VECTOR && vector != apic::ERROR_VECTOR
+}
+
+/// Appends a handler to the chain of the given vector.
+pub fn register<F>(vector: u8, handler: F) -> Result<IrqHandle, IrqError>
+    where F: Fn(&InterruptStackFrame) + Send + Sync + 'static {
+    if !is_dispatched(vector) {
+        return Err(IrqError::InvalidVector);
+    }
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let action = Action { id, handler: Box::new(handler) };
+
+    // The chain must not be locked by an interrupt handler while it is being modified.
+    instructions::interrupts::without_interrupts(|| ACTIONS[(vector - VECTOR_BASE) as usize].write().push(action));
+
+    Ok(IrqHandle { vector, id })
+}
+
+/// Removes a handler from the chain of its vector.
+pub fn unregister(handle: IrqHandle) -> Result<(), IrqError> {
+    if handle.vector < VECTOR_BASE {
+        return Err(IrqError::InvalidVector);
+    }
+
+    instructions::interrupts::without_interrupts(|| {
+        let mut actions = ACTIONS[(handle.vector - VECTOR_BASE) as usize].write();
+        let position = actions.iter().position(|action| action.id == handle.id).ok_or(IrqError::NotRegistered)?;
+        actions.remove(position);
+
+        Ok(())
+    })
+}
+
+/// Returns the number of handlers that are registered for the given vector.
+pub fn handler_count(vector: u8) -> usize {
+    if vector < VECTOR_BASE {
+        return 0;
+    }
+
+    ACTIONS[(vector - VECTOR_BASE) as usize].read().len()
+}
+
+pub fn init() -> Result<(), ()> {
+    lazy_static::initialize(&ACTIONS);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn register_and_unregister() {
+        let vector = 0xF0;
+
+        let first = register(vector, |_| {}).unwrap();
+        let second = register(vector, |_| {}).unwrap();
+        assert_eq!(handler_count(vector), 2);
+
+        unregister(first).unwrap();
+        assert_eq!(unregister(first), Err(IrqError::NotRegistered));
+        assert_eq!(handler_count(vector), 1);
+
+        unregister(second).unwrap();
+        assert_eq!(handler_count(vector), 0);
+    }
+
+    #[test_case]
+    fn exception_vectors_are_rejected() {
+        assert_eq!(register(0x0E, |_| {}).map(|handle| handle.vector()), Err(IrqError::InvalidVector));
+    }
+
+    #[test_case]
+    fn apic_vectors_are_rejected() {
+        for vector in [apic::SPURIOUS_VECTOR, apic::ERROR_VECTOR] {
+            assert_eq!(register(vector, |_| {}).map(|handle| handle.vector()), Err(IrqError::InvalidVector));
+            assert_eq!(handler_count(vector), 0);
+        }
+    }
+}
@@ -0,0 +1,347 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use core::iter;
+use core::mem;
+use core::ops::Range;
+use core::slice;
+
+use spin::Mutex;
+use x86_64::{PhysAddr, VirtAddr};
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Page, PageTableFlags, PhysFrame};
+use x86_64::structures::paging::Size4KiB;
+
+use super::elf;
+use super::heap;
+use super::paging;
+
+pub const FRAME_SIZE: usize = 4096;
+
+pub const HUGE_FRAME_SIZE: usize = 2 << 20;
+
+/// The bitmap is sized from the memory map at boot and mapped right below the kernel heap.
+///
+/// Memory beyond what a bitmap of the maximum size describes is ignored.
+pub const BITMAP_START: usize = 0xFFFF_FFFF_B000_0000;
+const BITMAP_MAX_SIZE: usize = heap::HEAP_START - BITMAP_START;
+
+const BITS_PER_WORD: usize = u64::BITS as usize;
+
+/// Physical Frame Allocator
+///
+/// A bitmap based allocator that hands out and reclaims 4 KiB physical frames. Each bit represents a single frame
+/// where a set bit marks the frame as free. Since the bitmap is zero-initialized, every frame is considered to be in
+/// use until the Multiboot2 memory map says otherwise. The bitmap itself is carved out of the first usable area that
+/// is large enough to hold it.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/Page_Frame_Allocation
+pub struct PhysicalFrameAllocator {
+    bitmap: &'static mut [u64],
+    next_word: usize,
+    free_frames: usize,
+    total_frames: usize,
+}
+
+impl PhysicalFrameAllocator {
+    const fn new() -> Self {
+        PhysicalFrameAllocator {
+            bitmap: &mut [],
+            next_word: 0,
+            free_frames: 0,
+            total_frames: 0,
+        }
+    }
+
+    /// Marks every frame that lies completely inside the given physical region as free.
+    fn release_region(&mut self, region: Range<usize>) {
+        let first = align_up(region.start) / FRAME_SIZE;
+        let last = (align_down(region.end) / FRAME_SIZE).min(self.bitmap.len() * BITS_PER_WORD);
+
+        for index in first..last {
+            if !self.is_free(index) {
+                self.set_free(index, true);
+                self.free_frames += 1;
+                self.total_frames += 1;
+            }
+        }
+    }
+
+    /// Marks every frame that overlaps with the given physical region as used.
+    fn reserve_region(&mut self, region: Range<usize>) {
+        let first = align_down(region.start) / FRAME_SIZE;
+        let last = (align_up(region.end) / FRAME_SIZE).min(self.bitmap.len() * BITS_PER_WORD);
+
+        for index in first..last {
+            if self.is_free(index) {
+                self.set_free(index, false);
+                self.free_frames -= 1;
+                self.total_frames -= 1;
+            }
+        }
+    }
+
+    /// Returns `true` if the bitmap describes the frame with the given index.
+    fn is_tracked(&self, index: usize) -> bool {
+        index < self.bitmap.len() * BITS_PER_WORD
+    }
+
+    fn is_free(&self, index: usize) -> bool {
+        self.bitmap[index / BITS_PER_WORD] & (1 << (index % BITS_PER_WORD)) != 0
+    }
+
+    fn set_free(&mut self, index: usize, free: bool) {
+        let mask = 1 << (index % BITS_PER_WORD);
+        if free {
+            self.bitmap[index / BITS_PER_WORD] |= mask;
+        } else {
+            self.bitmap[index / BITS_PER_WORD] &= !mask;
+        }
+    }
+
+    pub fn free_frames(&self) -> usize { self.free_frames }
+
+    pub fn total_frames(&self) -> usize { self.total_frames }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for PhysicalFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        // Resume the search from the word where the last frame was found, wrapping around once.
+        let bitmap_len = self.bitmap.len();
+        for offset in 0..bitmap_len {
+            let word_index = (self.next_word + offset) % bitmap_len;
+            let word = self.bitmap[word_index];
+            if word == 0 { continue; }
+
+            let index = word_index * BITS_PER_WORD + word.trailing_zeros() as usize;
+            self.set_free(index, false);
+            self.free_frames -= 1;
+            self.next_word = word_index;
+
+            return Some(PhysFrame::containing_address(PhysAddr::new((index * FRAME_SIZE) as u64)));
+        }
+
+        None
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for PhysicalFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        // Frames beyond the tracked memory, e.g. those of MMIO regions, were never handed out.
+        let index = frame.start_address().as_u64() as usize / FRAME_SIZE;
+        if !self.is_tracked(index) { return; }
+
+        assert!(!self.is_free(index), "frame {:#X} was freed twice", frame.start_address().as_u64());
+
+        self.set_free(index, true);
+        self.free_frames += 1;
+    }
+}
+
+/// Kernel Frame Allocator
+///
+/// A handle to the global frame allocator that can be passed wherever an implementation of the `FrameAllocator` or
+/// `FrameDeallocator` traits is expected.
+pub struct KernelFrameAllocator;
+
+unsafe impl FrameAllocator<Size4KiB> for KernelFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> { allocate_frame() }
+}
+
+impl FrameDeallocator<Size4KiB> for KernelFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) { deallocate_frame(frame) }
+}
+
+/// Bump Allocator
+///
+/// Hands out the frames of a region one after the other. It backs the bitmap of the frame allocator, which cannot
+/// allocate anything before its bitmap exists.
+struct BumpAllocator {
+    next: usize,
+    end: usize,
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BumpAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        if self.next + FRAME_SIZE > self.end {
+            return None;
+        }
+
+        let frame = PhysFrame::containing_address(PhysAddr::new(self.next as u64));
+        self.next += FRAME_SIZE;
+
+        Some(frame)
+    }
+}
+
+static FRAME_ALLOCATOR: Mutex<PhysicalFrameAllocator> = Mutex::new(PhysicalFrameAllocator::new());
+
+fn align_down(addr: usize) -> usize { addr & !(FRAME_SIZE - 1) }
+
+fn align_up(addr: usize) -> usize { align_down(addr + FRAME_SIZE - 1) }
+
+/// Translates a region that may live in the higher half into its physical counterpart.
+fn physical_region(region: Range<usize>) -> Range<usize> {
+    let offset = elf::kernel_offset();
+    if region.start >= offset {
+        (region.start - offset)..(region.end - offset)
+    } else {
+        region
+    }
+}
+
+/// Returns the physical regions that are occupied by the kernel image and the boot information.
+fn reserved_regions() -> [Range<usize>; 7] {
+    [
+        // The first frame holds the real mode IVT and its address doubles as the null pointer.
+        0..FRAME_SIZE,
+        elf::reserved_region(),
+        elf::prelude_region(),
+        physical_region(elf::kernel_region()),
+        physical_region(elf::multiboot_region()),
+        // The symbol table is needed to symbolize stack traces for as long as the kernel runs.
+        elf::symbol_table_region().unwrap_or_default(),
+        elf::string_table_region().unwrap_or_default(),
+    ]
+}
+
+/// Finds the lowest frame aligned range of the given size within the area that does not overlap a reserved region.
+fn free_range(area: Range<usize>, size: usize, reserved: &[Range<usize>]) -> Option<Range<usize>> {
+    let candidates = iter::once(area.start).chain(reserved.iter().map(|region| region.end));
+
+    candidates.map(|start| align_up(start)..align_up(start) + size)
+              .filter(|range| area.start <= range.start && range.end <= area.end)
+              .filter(|range| reserved.iter().all(|region| region.end <= range.start || range.end <= region.start))
+              .min_by_key(|range| range.start)
+}
+
+/// Maps a zeroed bitmap that describes the physical memory up to the given address.
+fn map_bitmap(memory_end: usize, reserved: &[Range<usize>]) -> Result<(&'static mut [u64], Range<usize>), ()> {
+    let frames = memory_end / FRAME_SIZE;
+    let bitmap_len = frames.div_ceil(BITS_PER_WORD).min(BITMAP_MAX_SIZE / mem::size_of::<u64>());
+    let bitmap_size = align_up(bitmap_len * mem::size_of::<u64>());
+
+    // Besides the bitmap itself, the region holds the page tables that map it, i.e. one for every 2 MiB.
+    let region_size = bitmap_size + (bitmap_size / HUGE_FRAME_SIZE + 2) * FRAME_SIZE;
+    let memory_map = elf::multiboot_info().memory_map_tag().ok_or(())?;
+    let region = memory_map.available_memory_areas()
+                           .map(|area| area.start_address() as usize..area.end_address() as usize)
+                           .find_map(|area| free_range(area, region_size, reserved))
+                           .ok_or(())?;
+
+    let mut frames = BumpAllocator { next: region.start, end: region.end };
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let first_page = Page::<Size4KiB>::containing_address(VirtAddr::new(BITMAP_START as u64));
+    for page in Page::range(first_page, first_page + (bitmap_size / FRAME_SIZE) as u64) {
+        let frame = frames.allocate_frame().ok_or(())?;
+        unsafe { paging::map_with(page, frame, flags, &mut frames).map_err(|_| ())?; }
+    }
+
+    let bitmap = unsafe { slice::from_raw_parts_mut(BITMAP_START as *mut u64, bitmap_len) };
+    bitmap.fill(0);
+
+    Ok((bitmap, region.start..frames.next))
+}
+
+pub fn init() -> Result<(), ()> {
+    let memory_map = elf::multiboot_info().memory_map_tag().ok_or(())?;
+    let memory_end = memory_map.available_memory_areas().map(|area| area.end_address() as usize).max().ok_or(())?;
+
+    // Frames that are occupied by the kernel image and the boot information must never be handed out.
+    let reserved = reserved_regions();
+    let (bitmap, bitmap_region) = map_bitmap(memory_end, &reserved)?;
+
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    allocator.bitmap = bitmap;
+
+    for area in memory_map.available_memory_areas() {
+        allocator.release_region(area.start_address() as usize..area.end_address() as usize);
+    }
+
+    for region in reserved.into_iter().chain([bitmap_region]) {
+        allocator.reserve_region(region);
+    }
+
+    log::info!(
+        "memory: {} KiB free in {} frames, bitmap of {} KiB at {:#X}",
+        allocator.free_frames() * FRAME_SIZE / 1024,
+        allocator.free_frames(),
+        mem::size_of_val(&*allocator.bitmap) / 1024,
+        BITMAP_START,
+    );
+
+    Ok(())
+}
+
+pub fn allocate_frame() -> Option<PhysFrame<Size4KiB>> {
+    FRAME_ALLOCATOR.lock().allocate_frame()
+}
+
+/// Returns a frame to the global frame allocator.
+///
+/// # Safety
+///
+/// The caller must guarantee that the frame is no longer in use.
+pub unsafe fn deallocate_frame(frame: PhysFrame<Size4KiB>) {
+    FRAME_ALLOCATOR.lock().deallocate_frame(frame);
+}
+
+pub fn free_frames() -> usize {
+    FRAME_ALLOCATOR.lock().free_frames()
+}
+
+pub fn total_frames() -> usize {
+    FRAME_ALLOCATOR.lock().total_frames()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn frame_allocation_round_trip() {
+        let frames_before = free_frames();
+
+        let frame = allocate_frame().expect("out of frames");
+        assert_eq!(free_frames(), frames_before - 1);
+
+        unsafe { deallocate_frame(frame); }
+        assert_eq!(free_frames(), frames_before);
+    }
+
+    #[test_case]
+    fn untracked_frames_are_ignored() {
+        let frames_before = free_frames();
+
+        let frame = PhysFrame::containing_address(PhysAddr::new(1 << 51));
+        unsafe { deallocate_frame(frame); }
+        assert_eq!(free_frames(), frames_before);
+    }
+
+    #[test_case]
+    fn bitmap_avoids_reserved_regions() {
+        let reserved = [0x1000..0x3000, 0x4800..0x5000];
+
+        assert_eq!(free_range(0x0..0x10000, 0x1000, &reserved), Some(0x0..0x1000));
+        assert_eq!(free_range(0x1000..0x10000, 0x2000, &reserved), Some(0x5000..0x7000));
+        assert_eq!(free_range(0x1000..0x6000, 0x2000, &reserved), None);
+    }
+}
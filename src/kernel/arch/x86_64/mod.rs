@@ -22,18 +22,61 @@
 
 use x86_64::instructions;
 
+use crate::kernel::cmdline;
+
+mod acpi;
 mod elf;
-mod exceptions;
-mod gdt;
+mod heap;
 mod idt;
+mod memory;
+mod paging;
+mod pit;
+mod symbols;
 
+pub mod apic;
+pub mod backtrace;
+pub mod cpu;
+pub mod exceptions;
+pub mod gdb;
+pub mod gdt;
+pub mod irq;
+pub mod monitor;
+pub mod pic;
+pub mod power;
+pub mod qemu;
+pub mod recovery;
 pub mod serial;
+pub mod trap;
+pub mod uart;
+pub mod watchpoint;
 
 pub fn init(boot_info_addr: usize) {
     elf::init(boot_info_addr).expect("kernel failed to retrieve metadata");
+    cmdline::init(elf::command_line()).expect("kernel failed to parse its command line");
+    paging::init().expect("kernel failed to take over page tables");
+    memory::init().expect("kernel failed to initialize frame allocator");
+    paging::protect_kernel().expect("kernel failed to protect its sections");
+    paging::unmap_stack_guard().expect("kernel failed to unmap the stack guard page");
+    heap::init().expect("kernel failed to initialize heap");
+    symbols::init().expect("kernel failed to load its symbol table");
 
     gdt::init().expect("kernel failed to initialize GDT");
+    irq::init().expect("kernel failed to initialize IRQ registry");
     idt::init().expect("kernel failed to initialize IDT");
+    pic::init().expect("kernel failed to initialize PIC");
+    acpi::init().expect("kernel failed to discover ACPI tables");
+    apic::init().expect("kernel failed to initialize APIC");
+    serial::init().expect("kernel failed to set up serial input");
+
+    paging::unmap_identity();
+}
+
+pub fn enable_interrupts() {
+    instructions::interrupts::enable();
+}
+
+pub fn disable_interrupts() {
+    instructions::interrupts::disable();
 }
 
 pub fn hlt_loop() -> ! {
@@ -0,0 +1,410 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use core::arch::asm;
+use core::ops::Range;
+
+use rustc_demangle::demangle;
+use x86_64::instructions::port::Port;
+use x86_64::instructions::tables::{sgdt, sidt};
+use x86_64::registers::model_specific::Msr;
+use x86_64::VirtAddr;
+
+use super::serial::{self, Role};
+use super::watchpoint::{self, WatchKind, WatchpointError};
+use super::{elf, gdb, gdt, memory, paging, symbols};
+use crate::console_print;
+use crate::console_println;
+use crate::kernel::cmdline;
+use crate::kernel::monitor::{parse_number, Command, CommandError};
+
+/// The most bytes that `mem` dumps at once.
+const MAX_DUMP_LENGTH: u64 = 4096;
+
+/// Commands that inspect and poke x86_64 specific state.
+pub const COMMANDS: &[Command] = &[
+    Command { name: "mem", usage: "mem <address> [length]", help: "dump virtual memory", run: mem },
+    Command { name: "walk", usage: "walk <address>", help: "walk the page tables of an address", run: walk },
+    Command { name: "idt", usage: "idt [first] [last]", help: "list the present IDT entries", run: idt },
+    Command { name: "gdt", usage: "gdt", help: "list the GDT entries", run: gdt },
+    Command { name: "mmap", usage: "mmap", help: "show the Multiboot memory map", run: mmap },
+    Command { name: "regions", usage: "regions", help: "show the regions of the kernel image", run: regions },
+    Command { name: "rdmsr", usage: "rdmsr <msr>", help: "read a model-specific register", run: rdmsr },
+    Command { name: "wrmsr", usage: "wrmsr <msr> <value>", help: "write a model-specific register", run: wrmsr },
+    Command { name: "in", usage: "in <port> [8|16|32]", help: "read an I/O port", run: port_in },
+    Command { name: "out", usage: "out <port> <value> [8|16|32]", help: "write an I/O port", run: port_out },
+    Command { name: "int3", usage: "int3", help: "trigger a breakpoint exception", run: int3 },
+    Command { name: "watch", usage: "watch [<address> <x|w|rw> [len]]", help: "set or list watchpoints", run: watch },
+    Command { name: "unwatch", usage: "unwatch <slot>", help: "clear a watchpoint", run: unwatch },
+    Command { name: "gdb", usage: "gdb [com<N>]", help: "stop and wait for GDB on the debugger port", run: gdb },
+];
+
+fn parse_address(word: &str) -> Result<VirtAddr, CommandError> {
+    VirtAddr::try_new(parse_number(word)?).map_err(|_| CommandError::Failed("address is not canonical"))
+}
+
+fn is_readable(addr: u64) -> bool {
+    VirtAddr::try_new(addr).is_ok_and(|addr| paging::walk(addr).is_mapped())
+}
+
+fn mem(args: &[&str]) -> Result<(), CommandError> {
+    let (start, length) = match args {
+        [address] => (parse_address(address)?, 64),
+        [address, length] => (parse_address(address)?, parse_number(length)?.min(MAX_DUMP_LENGTH)),
+        _ => return Err(CommandError::Usage),
+    };
+
+    let start = start.as_u64();
+    for row in (start..start.saturating_add(length)).step_by(16) {
+        let bytes: [Option<u8>; 16] = core::array::from_fn(|column| {
+            let addr = row.checked_add(column as u64)?;
+            is_readable(addr).then(|| unsafe { (addr as *const u8).read_volatile() })
+        });
+
+        console_print!("{:016X}: ", row);
+        for byte in bytes {
+            match byte {
+                Some(byte) => console_print!("{:02X} ", byte),
+                None => console_print!("?? "),
+            }
+        }
+        for byte in bytes {
+            match byte {
+                Some(byte @ 0x20..=0x7E) => console_print!("{}", byte as char),
+                _ => console_print!("."),
+            }
+        }
+        console_println!();
+    }
+
+    Ok(())
+}
+
+fn walk(args: &[&str]) -> Result<(), CommandError> {
+    let [address] = args else {
+        return Err(CommandError::Usage);
+    };
+
+    let addr = parse_address(address)?;
+    console_print!("{}", paging::walk(addr));
+
+    if let (Some(phys), Some(flags)) = (paging::translate(addr), paging::flags(addr)) {
+        console_println!("{:#X} -> {:#X} {}", addr.as_u64(), phys.as_u64(), paging::permissions(flags));
+    }
+
+    Ok(())
+}
+
+fn print_symbol(addr: u64) {
+    match symbols::resolve(addr) {
+        Some((name, offset)) => console_println!(" {:#}+{:#X}", demangle(name), offset),
+        None => console_println!(),
+    }
+}
+
+fn idt(args: &[&str]) -> Result<(), CommandError> {
+    let (first, last) = match args {
+        [] => (0, 255),
+        [vector] => (parse_number(vector)?, parse_number(vector)?),
+        [first, last] => (parse_number(first)?, parse_number(last)?),
+        _ => return Err(CommandError::Usage),
+    };
+
+    let pointer = sidt();
+    let count = (pointer.limit as u64 + 1) / 16;
+    let entries = pointer.base.as_ptr::<[u64; 2]>();
+
+    for vector in first..=last.min(count.saturating_sub(1)) {
+        let [low, high] = unsafe { entries.add(vector as usize).read() };
+
+        let attributes = (low >> 40) & 0xFF;
+        if attributes & 0x80 == 0 {
+            continue;
+        }
+
+        let offset = (low & 0xFFFF) | ((low >> 48) & 0xFFFF) << 16 | (high & 0xFFFF_FFFF) << 32;
+        let kind = if attributes & 0xF == 0xF { "trap" } else { "int" };
+
+        console_print!(
+            "[{:#04X}] cs={:#06X} ist={} {:<4} dpl={} {:#018X}",
+            vector, (low >> 16) & 0xFFFF, (low >> 32) & 0x7, kind, (attributes >> 5) & 0x3, offset,
+        );
+        print_symbol(offset);
+    }
+
+    Ok(())
+}
+
+fn gdt(args: &[&str]) -> Result<(), CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+
+    let pointer = sgdt();
+    let count = (pointer.limit as usize + 1) / 8;
+    let entries = pointer.base.as_ptr::<u64>();
+
+    let mut index = 0;
+    while index < count {
+        let entry = unsafe { entries.add(index).read() };
+        let selector = index * 8;
+        index += 1;
+
+        if entry == 0 {
+            console_println!("[{:#06X}] <null>", selector);
+            continue;
+        }
+
+        let present = (entry >> 47) & 0x1;
+        let dpl = (entry >> 45) & 0x3;
+        let kind = (entry >> 40) & 0xF;
+
+        // System segments, i.e. the TSS, take up two entries in long mode.
+        if (entry >> 44) & 0x1 == 0 && index < count {
+            let upper = unsafe { entries.add(index).read() };
+            index += 1;
+
+            let base = (entry >> 16) & 0xFF_FFFF | ((entry >> 56) & 0xFF) << 24 | (upper & 0xFFFF_FFFF) << 32;
+            let limit = entry & 0xFFFF | ((entry >> 48) & 0xF) << 16;
+            let busy = if kind == 0xB { " busy" } else { "" };
+            console_println!(
+                "[{:#06X}] tss  base={:#018X} limit={:#X} dpl={} p={}{}", selector, base, limit, dpl, present, busy,
+            );
+            continue;
+        }
+
+        let segment = if kind & 0x8 != 0 { "code" } else { "data" };
+        let long_mode = (entry >> 53) & 0x1;
+        console_println!("[{:#06X}] {} {:#018X} dpl={} p={} l={}", selector, segment, entry, dpl, present, long_mode);
+    }
+
+    Ok(())
+}
+
+fn mmap(args: &[&str]) -> Result<(), CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+
+    let memory_map = elf::multiboot_info().memory_map_tag().ok_or(CommandError::Failed("no memory map"))?;
+    for area in memory_map.memory_areas() {
+        console_println!(
+            "{:#018X}..{:#018X} {:>10} KiB {:?}",
+            area.start_address(), area.end_address(), area.size() / 1024, area.typ(),
+        );
+    }
+
+    let (free, total) = (memory::free_frames() * memory::FRAME_SIZE, memory::total_frames() * memory::FRAME_SIZE);
+    console_println!("{} of {} KiB usable memory free", free / 1024, total / 1024);
+
+    Ok(())
+}
+
+fn print_region(name: &str, region: Range<usize>) {
+    console_println!("{:<12} {:#018X}..{:#018X} {:>8} KiB", name, region.start, region.end, region.len() / 1024);
+}
+
+fn regions(args: &[&str]) -> Result<(), CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+
+    print_region("reserved", elf::reserved_region());
+    print_region("prelude", elf::prelude_region());
+    print_region("window", elf::kernel_window());
+    print_region("kernel", elf::kernel_region());
+    print_region(".text", elf::text_region());
+    print_region(".rodata", elf::rodata_region());
+    print_region(".data", elf::data_region());
+    print_region(".bss", elf::bss_region());
+    print_region(".got", elf::got_region());
+    print_region("stack guard", elf::stack_guard_region());
+    print_region("boot stack", elf::boot_stack_region());
+    print_region("multiboot", elf::multiboot_region());
+
+    for (name, region) in [("gdt", gdt::gdt_region()), ("tss", gdt::tss_region())] {
+        print_region(name, region.start.as_u64() as usize..region.end.as_u64() as usize);
+    }
+
+    if let Some(region) = elf::symbol_table_region() {
+        print_region(".symtab", region);
+    }
+    if let Some(region) = elf::string_table_region() {
+        print_region(".strtab", region);
+    }
+
+    Ok(())
+}
+
+fn parse_msr(word: &str) -> Result<Msr, CommandError> {
+    let index = u32::try_from(parse_number(word)?).map_err(|_| CommandError::InvalidNumber)?;
+
+    Ok(Msr::new(index))
+}
+
+fn rdmsr(args: &[&str]) -> Result<(), CommandError> {
+    let [msr] = args else {
+        return Err(CommandError::Usage);
+    };
+
+    // Reading an MSR that does not exist raises #GP, which the monitor catches.
+    let value = unsafe { parse_msr(msr)?.read() };
+    console_println!("{} = {:#018X}", msr, value);
+
+    Ok(())
+}
+
+fn wrmsr(args: &[&str]) -> Result<(), CommandError> {
+    let [msr, value] = args else {
+        return Err(CommandError::Usage);
+    };
+
+    unsafe { parse_msr(msr)?.write(parse_number(value)?); }
+
+    Ok(())
+}
+
+fn parse_port(word: &str) -> Result<u16, CommandError> {
+    u16::try_from(parse_number(word)?).map_err(|_| CommandError::InvalidNumber)
+}
+
+fn parse_width(args: &[&str]) -> Result<u8, CommandError> {
+    match args {
+        [] | ["8"] => Ok(8),
+        ["16"] => Ok(16),
+        ["32"] => Ok(32),
+        _ => Err(CommandError::Usage),
+    }
+}
+
+fn port_in(args: &[&str]) -> Result<(), CommandError> {
+    let Some((port, width)) = args.split_first() else {
+        return Err(CommandError::Usage);
+    };
+    let port = parse_port(port)?;
+
+    let value = unsafe {
+        match parse_width(width)? {
+            8 => Port::<u8>::new(port).read() as u32,
+            16 => Port::<u16>::new(port).read() as u32,
+            _ => Port::<u32>::new(port).read(),
+        }
+    };
+    console_println!("{:#06X} = {:#X}", port, value);
+
+    Ok(())
+}
+
+fn port_out(args: &[&str]) -> Result<(), CommandError> {
+    let [port, value, width @ ..] = args else {
+        return Err(CommandError::Usage);
+    };
+    let (port, value) = (parse_port(port)?, parse_number(value)?);
+
+    let width = parse_width(width)?;
+    if value >> width != 0 {
+        return Err(CommandError::Failed("value does not fit into the port"));
+    }
+
+    unsafe {
+        match width {
+            8 => Port::<u8>::new(port).write(value as u8),
+            16 => Port::<u16>::new(port).write(value as u16),
+            _ => Port::<u32>::new(port).write(value as u32),
+        }
+    }
+
+    Ok(())
+}
+
+fn int3(args: &[&str]) -> Result<(), CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::Usage);
+    }
+
+    unsafe { asm!("int3", options(nomem, nostack)); }
+
+    Ok(())
+}
+
+fn watch(args: &[&str]) -> Result<(), CommandError> {
+    let (addr, kind, length) = match args {
+        [] => {
+            for (slot, watchpoint) in watchpoint::watchpoints().iter().enumerate() {
+                if let Some(watchpoint) = watchpoint {
+                    let (addr, kind, len) = (watchpoint.addr.as_u64(), watchpoint.kind, watchpoint.len);
+                    console_println!("[{}] {:#018X} {:?} of {} bytes", slot, addr, kind, len);
+                }
+            }
+            return Ok(());
+        }
+        [addr, kind] => (parse_address(addr)?, *kind, 1),
+        [addr, kind, length] => (parse_address(addr)?, *kind, parse_number(length)?),
+        _ => return Err(CommandError::Usage),
+    };
+
+    let kind = match kind {
+        "x" => WatchKind::Execute,
+        "w" => WatchKind::Write,
+        "rw" => WatchKind::ReadWrite,
+        _ => return Err(CommandError::Usage),
+    };
+
+    let slot = watchpoint::set(addr, kind, length as usize).map_err(|error| match error {
+        WatchpointError::NoFreeSlot => CommandError::Failed("all debug registers are in use"),
+        WatchpointError::Misaligned => CommandError::Failed("address is not aligned to the length"),
+        _ => CommandError::Failed("length must be 1, 2, 4 or 8, and 1 for execute watchpoints"),
+    })?;
+    console_println!("watchpoint {} set", slot);
+
+    Ok(())
+}
+
+fn unwatch(args: &[&str]) -> Result<(), CommandError> {
+    let [slot] = args else {
+        return Err(CommandError::Usage);
+    };
+
+    watchpoint::clear(parse_number(slot)? as usize).map_err(|_| CommandError::Failed("no such slot"))
+}
+
+fn gdb(args: &[&str]) -> Result<(), CommandError> {
+    match *args {
+        [] => {}
+        [name] => {
+            let port = cmdline::parse_port(name).ok_or(CommandError::Usage)?;
+            serial::assign(Role::Debugger, port).map_err(|_| CommandError::Failed("the port does not exist"))?;
+        }
+        _ => return Err(CommandError::Usage),
+    }
+
+    gdb::enable().map_err(|_| CommandError::Failed("the debugger port does not exist"))?;
+
+    let port = serial::port_of(Role::Debugger);
+    console_println!("waiting for GDB on COM{}", port + 1);
+    if port == serial::port_of(Role::Console) {
+        console_println!("GDB shares the port with the console, `gdb com<N>` moves it to another one");
+    }
+
+    // The breakpoint stops in the stub, from where GDB takes over.
+    int3(&[])
+}
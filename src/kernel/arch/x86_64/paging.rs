@@ -0,0 +1,539 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use core::fmt;
+use core::ops::Range;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::{PhysAddr, VirtAddr};
+use x86_64::instructions::tlb;
+use x86_64::registers::control::{Cr3, Cr4, Cr4Flags};
+use x86_64::structures::paging::{FrameAllocator, Mapper, Page, PageSize, PageTable, PageTableFlags, PageTableIndex};
+use x86_64::structures::paging::PhysFrame;
+use x86_64::structures::paging::{RecursivePageTable, Size4KiB, Translate};
+use x86_64::structures::paging::frame::PhysFrameRangeInclusive;
+use x86_64::structures::paging::page_table::PageTableEntry;
+use x86_64::structures::paging::mapper::{FlagUpdateError, MapToError, TranslateResult, UnmapError};
+
+use super::elf;
+use super::memory::{self, KernelFrameAllocator};
+
+/// Index of the PML4 entry that points back to the PML4 itself (see `_set_up_page_tables` in the prelude).
+pub const RECURSIVE_INDEX: u16 = 510;
+
+/// Physical regions that the kernel does not own, e.g. firmware tables and MMIO registers, are mapped into this
+/// window on demand, right above the kernel heap.
+pub const PHYSICAL_WINDOW_START: u64 = 0xFFFF_FFFF_D000_0000;
+pub const PHYSICAL_WINDOW_SIZE: u64 = 256 << 20;
+
+extern "C" {
+    static _P4T: u8;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PagingError {
+    FrameAllocationFailed,
+    PageAlreadyMapped,
+    PageNotMapped,
+    ParentEntryHugePage,
+    InvalidFrameAddress,
+    WindowExhausted,
+}
+
+impl<S: PageSize> From<MapToError<S>> for PagingError {
+    fn from(err: MapToError<S>) -> Self {
+        match err {
+            MapToError::FrameAllocationFailed => PagingError::FrameAllocationFailed,
+            MapToError::ParentEntryHugePage => PagingError::ParentEntryHugePage,
+            MapToError::PageAlreadyMapped(_) => PagingError::PageAlreadyMapped,
+        }
+    }
+}
+
+impl From<UnmapError> for PagingError {
+    fn from(err: UnmapError) -> Self {
+        match err {
+            UnmapError::ParentEntryHugePage => PagingError::ParentEntryHugePage,
+            UnmapError::PageNotMapped => PagingError::PageNotMapped,
+            UnmapError::InvalidFrameAddress(_) => PagingError::InvalidFrameAddress,
+        }
+    }
+}
+
+impl From<FlagUpdateError> for PagingError {
+    fn from(err: FlagUpdateError) -> Self {
+        match err {
+            FlagUpdateError::PageNotMapped => PagingError::PageNotMapped,
+            FlagUpdateError::ParentEntryHugePage => PagingError::ParentEntryHugePage,
+        }
+    }
+}
+
+/// Page Table Walk
+///
+/// A snapshot of the entries that the MMU visits while translating a virtual address, from the PML4 down to either
+/// the entry that maps the page or the first entry that is not present.
+pub struct PageWalk {
+    addr: VirtAddr,
+    entries: [Option<PageTableEntry>; 4],
+}
+
+impl PageWalk {
+    const LEVELS: [&'static str; 4] = ["PML4", "PDPT", "PD", "PT"];
+
+    fn indices(&self) -> [PageTableIndex; 4] {
+        [self.addr.p4_index(), self.addr.p3_index(), self.addr.p2_index(), self.addr.p1_index()]
+    }
+
+    /// Returns `true` if the walk ended in a present entry that maps the page.
+    pub fn is_mapped(&self) -> bool {
+        let mut entries = self.entries.iter().enumerate().filter_map(|(level, entry)| Some((level, entry.as_ref()?)));
+
+        entries.next_back().is_some_and(|(level, entry)| {
+            let flags = entry.flags();
+            flags.contains(PageTableFlags::PRESENT) && (level == 3 || flags.contains(PageTableFlags::HUGE_PAGE))
+        })
+    }
+}
+
+impl fmt::Display for PageWalk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "page table walk for {:#X}:", self.addr.as_u64())?;
+
+        for ((level, index), entry) in Self::LEVELS.iter().zip(self.indices()).zip(&self.entries) {
+            let Some(entry) = entry else { break; };
+
+            if entry.is_unused() {
+                writeln!(f, "  {:<4}[{:>3}] = <unused>", level, u16::from(index))?;
+            } else {
+                let (addr, flags) = (entry.addr().as_u64(), entry.flags());
+                writeln!(f, "  {:<4}[{:>3}] = {:#018X} {:?}", level, u16::from(index), addr, flags)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+static NEXT_WINDOW_PAGE: AtomicU64 = AtomicU64::new(PHYSICAL_WINDOW_START);
+
+lazy_static! {
+    /// Kernel Page Table
+    ///
+    /// The four-level page table set up by the prelude at `_P4T`. Since the last but one entry of the PML4 points
+    /// back to the PML4 itself, every table of the hierarchy is reachable through a fixed virtual address, which
+    /// allows the mappings to be modified without an identity mapping of the physical memory.
+    ///
+    /// OS Dev Wiki: https://wiki.osdev.org/Page_Tables
+    static ref PAGE_TABLE: Mutex<RecursivePageTable<'static>> = {
+        let index = PageTableIndex::new(RECURSIVE_INDEX);
+        let page = Page::from_page_table_indices(index, index, index, index);
+
+        let p4 = unsafe { &mut *page.start_address().as_mut_ptr::<PageTable>() };
+        let page_table = RecursivePageTable::new(p4).expect("PML4 is not recursively mapped");
+
+        Mutex::new(page_table)
+    };
+}
+
+/// Physical address of the PML4 reserved by the prelude.
+fn p4_table_address() -> PhysAddr {
+    PhysAddr::new((unsafe { &_P4T as *const u8 as usize } - elf::kernel_offset()) as u64)
+}
+
+pub fn init() -> Result<(), ()> {
+    // Make sure that the processor is still using the page tables that were set up by the prelude.
+    let (p4_frame, _) = Cr3::read();
+    if p4_frame.start_address() != p4_table_address() {
+        return Err(());
+    }
+
+    lazy_static::initialize(&PAGE_TABLE);
+
+    Ok(())
+}
+
+/// Maps the given page to the given frame.
+///
+/// # Safety
+///
+/// The caller must guarantee that the frame is not in use elsewhere and that the new mapping does not break memory
+/// safety, e.g. by aliasing memory that is already mapped.
+pub unsafe fn map<S: PageSize>(page: Page<S>, frame: PhysFrame<S>, flags: PageTableFlags) -> Result<(), PagingError>
+    where RecursivePageTable<'static>: Mapper<S> {
+    map_with(page, frame, flags, &mut KernelFrameAllocator)
+}
+
+/// Maps the given page to the given frame, taking the frames for missing page tables from the given allocator.
+///
+/// # Safety
+///
+/// See `map`. Additionally, the frames that the allocator hands out must not be in use elsewhere.
+pub unsafe fn map_with<S: PageSize, A: FrameAllocator<Size4KiB>>(
+    page: Page<S>,
+    frame: PhysFrame<S>,
+    flags: PageTableFlags,
+    allocator: &mut A,
+) -> Result<(), PagingError> where RecursivePageTable<'static>: Mapper<S> {
+    PAGE_TABLE.lock().map_to(page, frame, flags, allocator)?.flush();
+
+    Ok(())
+}
+
+/// Unmaps the given page and returns the frame it was mapped to.
+///
+/// # Safety
+///
+/// The caller must guarantee that the page is no longer referenced.
+pub unsafe fn unmap<S: PageSize>(page: Page<S>) -> Result<PhysFrame<S>, PagingError>
+    where RecursivePageTable<'static>: Mapper<S> {
+    let (frame, flush) = PAGE_TABLE.lock().unmap(page)?;
+    flush.flush();
+
+    Ok(frame)
+}
+
+/// Replaces the flags of the given page.
+///
+/// # Safety
+///
+/// The caller must guarantee that the new flags do not break memory safety, e.g. by revoking write access to memory
+/// that is still being written to.
+pub unsafe fn protect<S: PageSize>(page: Page<S>, flags: PageTableFlags) -> Result<(), PagingError>
+    where RecursivePageTable<'static>: Mapper<S> {
+    PAGE_TABLE.lock().update_flags(page, flags)?.flush();
+
+    Ok(())
+}
+
+/// Translates the given virtual address to the physical address it is mapped to.
+pub fn translate(addr: VirtAddr) -> Option<PhysAddr> {
+    PAGE_TABLE.lock().translate_addr(addr)
+}
+
+/// Returns the flags of the page that contains the given virtual address.
+pub fn flags(addr: VirtAddr) -> Option<PageTableFlags> {
+    match PAGE_TABLE.lock().translate(addr) {
+        TranslateResult::Mapped { flags, .. } => Some(flags),
+        _ => None,
+    }
+}
+
+/// Walks the page tables that translate the given virtual address.
+///
+/// The tables are read through the recursive mapping without acquiring the page table lock, so that the walk can be
+/// performed from within a fault handler, even if the fault occurred while the page tables were being modified.
+pub fn walk(addr: VirtAddr) -> PageWalk {
+    let recursive_index = PageTableIndex::new(RECURSIVE_INDEX);
+
+    let mut walk = PageWalk { addr, entries: [None, None, None, None] };
+    let indices = walk.indices();
+
+    for level in 0..indices.len() {
+        // Each level down, the recursive entry is traversed one time less to reach the table of that level.
+        let mut table_indices = [recursive_index; 4];
+        table_indices[4 - level..].copy_from_slice(&indices[..level]);
+
+        let [p4_index, p3_index, p2_index, p1_index] = table_indices;
+        let table_page = Page::<Size4KiB>::from_page_table_indices(p4_index, p3_index, p2_index, p1_index);
+        let table = unsafe { &*table_page.start_address().as_ptr::<PageTable>() };
+
+        let entry = table[indices[level]].clone();
+        let flags = entry.flags();
+        walk.entries[level] = Some(entry);
+
+        if !flags.contains(PageTableFlags::PRESENT) || flags.contains(PageTableFlags::HUGE_PAGE) {
+            break;
+        }
+    }
+
+    walk
+}
+
+/// Backs the given virtual region with freshly allocated 4 KiB frames.
+pub fn map_region(region: Range<VirtAddr>, flags: PageTableFlags) -> Result<(), PagingError> {
+    let first_page = Page::<Size4KiB>::containing_address(region.start);
+    let last_page = Page::<Size4KiB>::containing_address(region.end - 1u64);
+
+    for page in Page::range_inclusive(first_page, last_page) {
+        let frame = memory::allocate_frame().ok_or(PagingError::FrameAllocationFailed);
+        let mapped = frame.and_then(|frame| unsafe {
+            map(page, frame, flags).inspect_err(|_| memory::deallocate_frame(frame))
+        });
+
+        // Nothing of a region that cannot be mapped completely stays mapped, so that its frames are not leaked.
+        if let Err(err) = mapped {
+            if page != first_page {
+                unsafe { unmap_region(first_page.start_address()..page.start_address())?; }
+            }
+            return Err(err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Unmaps the given virtual region and returns its frames to the frame allocator.
+///
+/// # Safety
+///
+/// The caller must guarantee that the region was mapped by `map_region` and is no longer referenced.
+pub unsafe fn unmap_region(region: Range<VirtAddr>) -> Result<(), PagingError> {
+    let first_page = Page::<Size4KiB>::containing_address(region.start);
+    let last_page = Page::<Size4KiB>::containing_address(region.end - 1u64);
+
+    for page in Page::range_inclusive(first_page, last_page) {
+        memory::deallocate_frame(unmap(page)?);
+    }
+
+    Ok(())
+}
+
+/// Replaces the flags of every 4 KiB page in the given virtual region.
+///
+/// # Safety
+///
+/// See `protect`.
+pub unsafe fn protect_region(region: Range<VirtAddr>, flags: PageTableFlags) -> Result<(), PagingError> {
+    let first_page = Page::<Size4KiB>::containing_address(region.start);
+    let last_page = Page::<Size4KiB>::containing_address(region.end - 1u64);
+
+    for page in Page::range_inclusive(first_page, last_page) {
+        protect(page, flags)?;
+    }
+
+    Ok(())
+}
+
+/// Maps the given physical region into the physical window and returns the virtual address of its first byte.
+///
+/// The frames are not taken from the frame allocator and the mapping is never torn down, so this is only meant for
+/// regions that stay in use for the lifetime of the kernel.
+///
+/// # Safety
+///
+/// The caller must guarantee that the region is not owned by the frame allocator and that the flags suit it, e.g.
+/// that MMIO registers are mapped as uncacheable.
+pub unsafe fn map_physical(addr: PhysAddr, size: usize, flags: PageTableFlags) -> Result<VirtAddr, PagingError> {
+    let first_frame = PhysFrame::<Size4KiB>::containing_address(addr);
+    let last_frame = PhysFrame::<Size4KiB>::containing_address(addr + (size.max(1) - 1) as u64);
+    let window_size = last_frame.start_address() - first_frame.start_address() + Size4KiB::SIZE;
+
+    let start = NEXT_WINDOW_PAGE.fetch_add(window_size, Ordering::Relaxed);
+    if start + window_size > PHYSICAL_WINDOW_START + PHYSICAL_WINDOW_SIZE {
+        return Err(PagingError::WindowExhausted);
+    }
+
+    map_frames(VirtAddr::new(start), PhysFrame::range_inclusive(first_frame, last_frame), flags)?;
+
+    Ok(VirtAddr::new(start) + (addr - first_frame.start_address()))
+}
+
+/// Maps a physical structure whose size is only known once its header has been read, e.g. an ACPI table.
+///
+/// The pages that hold the header are mapped first, then `size` is called with its virtual address. The pages that
+/// the structure needs beyond them are mapped right behind, so that every page is mapped once.
+///
+/// # Safety
+///
+/// See `map_physical`.
+pub unsafe fn map_physical_sized<F: FnOnce(VirtAddr) -> usize>(
+    addr: PhysAddr,
+    header_size: usize,
+    size: F,
+    flags: PageTableFlags,
+) -> Result<VirtAddr, PagingError> {
+    let header = map_physical(addr, header_size, flags)?;
+
+    let size = size(header).max(header_size);
+
+    let first_frame = PhysFrame::<Size4KiB>::containing_address(addr);
+    let next_frame = PhysFrame::<Size4KiB>::containing_address(addr + (header_size.max(1) - 1) as u64) + 1;
+    let last_frame = PhysFrame::<Size4KiB>::containing_address(addr + (size - 1) as u64);
+    if last_frame < next_frame {
+        return Ok(header);
+    }
+
+    // The remaining pages can only be appended as long as the header is still the last mapping of the window.
+    let header_end = header.align_down(Size4KiB::SIZE).as_u64() + (next_frame - first_frame) * Size4KiB::SIZE;
+    let end = header_end + (last_frame - next_frame + 1) * Size4KiB::SIZE;
+    if NEXT_WINDOW_PAGE.compare_exchange(header_end, end, Ordering::Relaxed, Ordering::Relaxed).is_err() {
+        return map_physical(addr, size, flags);
+    }
+    if end > PHYSICAL_WINDOW_START + PHYSICAL_WINDOW_SIZE {
+        return Err(PagingError::WindowExhausted);
+    }
+
+    map_frames(VirtAddr::new(header_end), PhysFrame::range_inclusive(next_frame, last_frame), flags)?;
+
+    Ok(header)
+}
+
+/// Maps consecutive frames to consecutive pages, starting at the given page aligned address.
+unsafe fn map_frames(
+    start: VirtAddr,
+    frames: PhysFrameRangeInclusive,
+    flags: PageTableFlags,
+) -> Result<(), PagingError> {
+    let first_page = Page::<Size4KiB>::containing_address(start);
+
+    for (offset, frame) in frames.enumerate() {
+        map(first_page + offset as u64, frame, flags)?;
+    }
+
+    Ok(())
+}
+
+/// Remaps the sections of the kernel image with the least permissions they require.
+///
+/// The prelude maps the whole kernel window as writable and executable. Since the linker script aligns every
+/// section to a page boundary, each of them can be given its own permissions so that no page is both writable and
+/// executable (W^X).
+pub fn protect_kernel() -> Result<(), PagingError> {
+    use PageTableFlags as Flags;
+
+    let sections = [
+        (".text", elf::text_region(), Flags::PRESENT),
+        (".rodata", elf::rodata_region(), Flags::PRESENT | Flags::NO_EXECUTE),
+        (".data", elf::data_region(), Flags::PRESENT | Flags::WRITABLE | Flags::NO_EXECUTE),
+        (".bss", elf::bss_region(), Flags::PRESENT | Flags::WRITABLE | Flags::NO_EXECUTE),
+        (".got", elf::got_region(), Flags::PRESENT | Flags::NO_EXECUTE),
+    ];
+
+    // Whatever else is visible through the kernel window (e.g. low memory and the boot information) is data.
+    let kernel_region = elf::kernel_region();
+    let data_flags = Flags::PRESENT | Flags::WRITABLE | Flags::NO_EXECUTE;
+    for addr in elf::kernel_window().step_by(memory::FRAME_SIZE).filter(|addr| !kernel_region.contains(addr)) {
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(addr as u64));
+        match unsafe { protect(page, data_flags) } {
+            Ok(()) | Err(PagingError::PageNotMapped) => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    for (name, region, flags) in sections {
+        if !region.is_empty() {
+            unsafe { protect_region(VirtAddr::new(region.start as u64)..VirtAddr::new(region.end as u64), flags)?; }
+        }
+
+        log::info!("kernel: {:<8} {:#X}..{:#X} {}", name, region.start, region.end, permissions(flags));
+    }
+
+    Ok(())
+}
+
+/// Formats the access rights granted by the given flags in the usual `rwx` notation.
+pub fn permissions(flags: PageTableFlags) -> &'static str {
+    let writable = flags.contains(PageTableFlags::WRITABLE);
+    let executable = !flags.contains(PageTableFlags::NO_EXECUTE);
+
+    match (writable, executable) {
+        (false, false) => "r--",
+        (false, true) => "r-x",
+        (true, false) => "rw-",
+        (true, true) => "rwx",
+    }
+}
+
+/// Unmaps the guard page below the boot stack.
+///
+/// The prelude reserves the page in the BSS, right above the page tables. Once it is unmapped, a stack overflow
+/// raises a page fault instead of silently overwriting `_P1T_K`.
+pub fn unmap_stack_guard() -> Result<(), PagingError> {
+    let guard_region = elf::stack_guard_region();
+    let first_page = Page::<Size4KiB>::containing_address(VirtAddr::new(guard_region.start as u64));
+    let last_page = Page::<Size4KiB>::containing_address(VirtAddr::new(guard_region.end as u64 - 1));
+
+    // The frames belong to the kernel image, hence they are not returned to the frame allocator.
+    for page in Page::range_inclusive(first_page, last_page) {
+        unsafe { unmap(page)?; }
+    }
+
+    Ok(())
+}
+
+/// Removes the identity mapping of the low memory that the prelude needed to enable paging.
+///
+/// The prelude already moves the stack pointer and the boot information pointer into the higher half before it
+/// calls `k_main`, and the GDT is reloaded from the higher half, so the kernel no longer depends on the identity
+/// mapping. Without it, dereferencing a null or any other low address faults instead of corrupting memory.
+pub fn unmap_identity() {
+    PAGE_TABLE.lock().level_4_table()[0].set_unused();
+
+    flush_all();
+}
+
+/// Invalidates the entire TLB, including global pages.
+pub fn flush_all() {
+    let cr4 = Cr4::read();
+    if cr4.contains(Cr4Flags::PAGE_GLOBAL) {
+        // Reloading CR3 leaves global pages untouched, whereas toggling CR4.PGE evicts them as well.
+        unsafe {
+            Cr4::write(cr4 - Cr4Flags::PAGE_GLOBAL);
+            Cr4::write(cr4);
+        }
+    } else {
+        tlb::flush_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::heap;
+
+    #[test_case]
+    fn kernel_is_mapped_at_its_offset() {
+        let text = elf::text_region();
+        let addr = VirtAddr::new(text.start as u64);
+
+        assert_eq!(translate(addr), Some(PhysAddr::new((text.start - elf::kernel_offset()) as u64)));
+    }
+
+    #[test_case]
+    fn identity_mapping_is_gone() {
+        assert_eq!(translate(VirtAddr::new(0x1000)), None);
+    }
+
+    #[test_case]
+    fn partially_mapped_region_is_released() {
+        // The last page of the region is the first page of the heap, which is mapped already.
+        let heap_start = VirtAddr::new(heap::HEAP_START as u64);
+        let region = heap_start - 2 * Size4KiB::SIZE..heap_start + 1u64;
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+
+        assert_eq!(map_region(region.clone(), flags), Err(PagingError::PageAlreadyMapped));
+        assert_eq!(translate(region.start), None);
+        assert!(translate(heap_start).is_some());
+    }
+
+    #[test_case]
+    fn sections_follow_w_xor_x() {
+        let text_flags = flags(VirtAddr::new(elf::text_region().start as u64)).unwrap();
+        assert_eq!(permissions(text_flags), "r-x");
+
+        let bss_flags = flags(VirtAddr::new(elf::bss_region().start as u64)).unwrap();
+        assert_eq!(permissions(bss_flags), "rw-");
+    }
+}
@@ -0,0 +1,227 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use spin::Mutex;
+use x86_64::instructions;
+use x86_64::instructions::port::Port;
+
+use super::irq::{self, InterruptController};
+
+/// The vector that the first line of the master PIC is remapped to.
+pub const MASTER_OFFSET: u8 = irq::VECTOR_BASE;
+/// The vector that the first line of the slave PIC is remapped to.
+pub const SLAVE_OFFSET: u8 = MASTER_OFFSET + LINES_PER_PIC;
+
+pub const LINES_PER_PIC: u8 = 8;
+pub const LINE_COUNT: u8 = irq::LEGACY_IRQ_COUNT;
+
+/// The line of the master PIC that the slave PIC is cascaded to.
+const CASCADE_LINE: u8 = 2;
+/// The lowest priority line of each PIC, on which spurious interrupts are reported.
+const SPURIOUS_LINE: u8 = 7;
+
+const ICW1_ICW4: u8 = 0x01;
+const ICW1_INIT: u8 = 0x10;
+const ICW4_8086: u8 = 0x01;
+const OCW3_READ_ISR: u8 = 0x0B;
+const EOI: u8 = 0x20;
+
+/// Programmable Interrupt Controller (8259 PIC)
+///
+/// A single 8259 chip, which multiplexes eight interrupt lines onto a single interrupt pin of the processor.
+struct Pic {
+    offset: u8,
+    command: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Pic {
+    const fn new(offset: u8, command: u16, data: u16) -> Self {
+        Pic { offset, command: Port::new(command), data: Port::new(data) }
+    }
+
+    fn handles(&self, vector: u8) -> bool {
+        (self.offset..self.offset + LINES_PER_PIC).contains(&vector)
+    }
+
+    fn in_service(&mut self) -> u8 {
+        unsafe {
+            self.command.write(OCW3_READ_ISR);
+            self.command.read()
+        }
+    }
+
+    fn end_of_interrupt(&mut self) {
+        unsafe { self.command.write(EOI); }
+    }
+
+    fn read_mask(&mut self) -> u8 {
+        unsafe { self.data.read() }
+    }
+
+    fn write_mask(&mut self, mask: u8) {
+        unsafe { self.data.write(mask); }
+    }
+}
+
+/// Chained 8259 PICs
+///
+/// On the PC/AT, two 8259 chips are chained together: the slave is cascaded to line 2 of the master, which gives a
+/// total of 15 usable lines. By default, the master delivers its interrupts on vectors 0x08 to 0x0F, which collide
+/// with CPU exceptions in protected mode, so both chips must be remapped before interrupts are enabled.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/8259_PIC
+pub struct ChainedPics {
+    master: Pic,
+    slave: Pic,
+}
+
+impl ChainedPics {
+    const fn new() -> Self {
+        ChainedPics {
+            master: Pic::new(MASTER_OFFSET, 0x20, 0x21),
+            slave: Pic::new(SLAVE_OFFSET, 0xA0, 0xA1),
+        }
+    }
+
+    fn remap(&mut self) {
+        // Writing to an unused port gives the PICs enough time to react on older machines.
+        let mut wait_port: Port<u8> = Port::new(0x80);
+        let mut wait = || unsafe { wait_port.write(0) };
+
+        unsafe {
+            // ICW1: Start the initialization sequence in cascade mode.
+            self.master.command.write(ICW1_INIT | ICW1_ICW4);
+            wait();
+            self.slave.command.write(ICW1_INIT | ICW1_ICW4);
+            wait();
+
+            // ICW2: Set the vector offsets.
+            self.master.data.write(self.master.offset);
+            wait();
+            self.slave.data.write(self.slave.offset);
+            wait();
+
+            // ICW3: Tell the master where the slave is cascaded and the slave its cascade identity.
+            self.master.data.write(1 << CASCADE_LINE);
+            wait();
+            self.slave.data.write(CASCADE_LINE);
+            wait();
+
+            // ICW4: Use 8086 mode.
+            self.master.data.write(ICW4_8086);
+            wait();
+            self.slave.data.write(ICW4_8086);
+            wait();
+        }
+
+        // Mask every line but the cascade, the drivers unmask the lines they are interested in.
+        self.master.write_mask(!(1 << CASCADE_LINE));
+        self.slave.write_mask(0xFF);
+    }
+
+    fn set_masked(&mut self, line: u8, masked: bool) {
+        let (pic, bit) = if line < LINES_PER_PIC {
+            (&mut self.master, line)
+        } else {
+            (&mut self.slave, line - LINES_PER_PIC)
+        };
+
+        let mask = pic.read_mask();
+        pic.write_mask(if masked { mask | (1 << bit) } else { mask & !(1 << bit) });
+    }
+
+    fn is_spurious(&mut self, vector: u8) -> bool {
+        // A spurious interrupt is reported on the lowest priority line, without the line being in service.
+        if vector == self.master.offset + SPURIOUS_LINE {
+            return self.master.in_service() & (1 << SPURIOUS_LINE) == 0;
+        }
+
+        if vector == self.slave.offset + SPURIOUS_LINE && self.slave.in_service() & (1 << SPURIOUS_LINE) == 0 {
+            // The master does not know that the interrupt of the slave was spurious, so it still expects an EOI.
+            self.master.end_of_interrupt();
+            return true;
+        }
+
+        false
+    }
+
+    fn end_of_interrupt(&mut self, vector: u8) {
+        if self.slave.handles(vector) {
+            self.slave.end_of_interrupt();
+        }
+
+        if self.master.handles(vector) || self.slave.handles(vector) {
+            self.master.end_of_interrupt();
+        }
+    }
+}
+
+static PICS: Mutex<ChainedPics> = Mutex::new(ChainedPics::new());
+
+/// Interrupt controller backed by the legacy PICs.
+pub struct LegacyPic;
+
+impl InterruptController for LegacyPic {
+    fn is_spurious(&self, vector: u8) -> bool {
+        PICS.lock().is_spurious(vector)
+    }
+
+    fn end_of_interrupt(&self, vector: u8) {
+        PICS.lock().end_of_interrupt(vector);
+    }
+
+    fn set_masked(&self, line: u8, masked: bool) {
+        if masked { mask(line) } else { unmask(line) }
+    }
+}
+
+/// Stops the given line from raising interrupts.
+pub fn mask(line: u8) {
+    assert!(line < LINE_COUNT, "PIC line {} does not exist", line);
+
+    instructions::interrupts::without_interrupts(|| PICS.lock().set_masked(line, true));
+}
+
+/// Allows the given line to raise interrupts.
+pub fn unmask(line: u8) {
+    assert!(line < LINE_COUNT, "PIC line {} does not exist", line);
+
+    instructions::interrupts::without_interrupts(|| PICS.lock().set_masked(line, false));
+}
+
+/// Masks every line of both PICs, e.g. when the APIC takes over.
+pub fn disable() {
+    instructions::interrupts::without_interrupts(|| {
+        let mut pics = PICS.lock();
+        pics.master.write_mask(0xFF);
+        pics.slave.write_mask(0xFF);
+    });
+}
+
+pub fn init() -> Result<(), ()> {
+    PICS.lock().remap();
+
+    irq::set_controller(&LegacyPic);
+
+    Ok(())
+}
@@ -0,0 +1,65 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use x86_64::instructions::port::Port;
+
+/// The frequency of the oscillator that drives every channel of the PIT.
+const FREQUENCY: u32 = 1_193_182;
+
+const CHANNEL_2_DATA_PORT: u16 = 0x42;
+const COMMAND_PORT: u16 = 0x43;
+/// The port of the system control register that gates channel 2 and reports its output.
+const CONTROL_PORT: u16 = 0x61;
+
+/// Channel 2, low byte then high byte of the count, interrupt on terminal count (mode 0), binary.
+const CHANNEL_2_ONE_SHOT: u8 = 0b1011_0000;
+const CONTROL_GATE_2: u8 = 1 << 0;
+const CONTROL_SPEAKER: u8 = 1 << 1;
+const CONTROL_OUTPUT_2: u8 = 1 << 5;
+
+/// Programmable Interval Timer (8253/8254 PIT)
+///
+/// Busy-waits for the given number of milliseconds by counting down channel 2, which is not wired to an interrupt
+/// line. The delay therefore also works with interrupts disabled, e.g. while the machine is powered off or reset.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/Programmable_Interval_Timer
+pub fn delay(milliseconds: u32) {
+    let mut control: Port<u8> = Port::new(CONTROL_PORT);
+    let mut command: Port<u8> = Port::new(COMMAND_PORT);
+    let mut data: Port<u8> = Port::new(CHANNEL_2_DATA_PORT);
+    let count = (FREQUENCY / 1000) as u16;
+
+    for _ in 0..milliseconds {
+        unsafe {
+            // Open the gate of channel 2 without driving the speaker, then restart its count.
+            let value = control.read();
+            control.write(value & !CONTROL_SPEAKER | CONTROL_GATE_2);
+            command.write(CHANNEL_2_ONE_SHOT);
+            data.write(count as u8);
+            data.write((count >> 8) as u8);
+
+            while control.read() & CONTROL_OUTPUT_2 == 0 {
+                core::hint::spin_loop();
+            }
+        }
+    }
+}
@@ -0,0 +1,199 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use core::arch::asm;
+use core::ptr;
+
+use x86_64::{instructions, PhysAddr, VirtAddr};
+use x86_64::instructions::port::Port;
+use x86_64::instructions::tables::{DescriptorTablePointer, lidt};
+use x86_64::structures::paging::PageTableFlags;
+
+use super::acpi::{self, Fadt, GenericAddress};
+use super::{paging, pit};
+use crate::serial_println;
+
+const SLP_TYP_SHIFT: u16 = 10;
+const SLP_EN: u16 = 1 << 13;
+const SCI_EN: u16 = 1 << 0;
+
+/// How long the chipset may take to switch into ACPI mode, in milliseconds.
+const ACPI_ENABLE_TIMEOUT: u32 = 3000;
+/// How long the hardware may take to react to a shutdown or reset before the next method is tried, in milliseconds.
+const POWER_TIMEOUT: u32 = 100;
+
+const KBC_STATUS_PORT: u16 = 0x64;
+const KBC_INPUT_FULL: u8 = 1 << 1;
+const KBC_PULSE_RESET: u8 = 0xFE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerError {
+    NoFadt,
+    NoSleepState,
+    NoResetRegister,
+    UnsupportedAddressSpace,
+    AcpiNotEnabled,
+}
+
+/// Returns the port of a PM1 control block, preferring the extended field.
+fn pm1_control_port(block: u32, x_block: GenericAddress) -> Result<Option<Port<u16>>, PowerError> {
+    if x_block.is_present() {
+        if x_block.address_space != GenericAddress::SYSTEM_IO {
+            return Err(PowerError::UnsupportedAddressSpace);
+        }
+
+        return Ok(Some(Port::new(x_block.address as u16)));
+    }
+
+    Ok((block != 0).then(|| Port::new(block as u16)))
+}
+
+/// Switches the chipset from legacy mode into ACPI mode, unless the firmware already did. Gives up if `SCI_EN` is
+/// not set within `ACPI_ENABLE_TIMEOUT`.
+fn enable_acpi(fadt: &Fadt, pm1a_control: &mut Port<u16>) -> Result<(), PowerError> {
+    if unsafe { pm1a_control.read() } & SCI_EN != 0 {
+        return Ok(());
+    }
+
+    if fadt.smi_command_port == 0 || fadt.acpi_enable == 0 {
+        return Err(PowerError::AcpiNotEnabled);
+    }
+
+    unsafe { Port::<u8>::new(fadt.smi_command_port as u16).write(fadt.acpi_enable); }
+
+    for _ in 0..ACPI_ENABLE_TIMEOUT {
+        if unsafe { pm1a_control.read() } & SCI_EN != 0 {
+            return Ok(());
+        }
+        pit::delay(1);
+    }
+
+    Err(PowerError::AcpiNotEnabled)
+}
+
+/// Enters the soft off state (S5) by writing its `SLP_TYP` along with `SLP_EN` to the PM1 control blocks.
+fn acpi_shutdown() -> Result<(), PowerError> {
+    let fadt = acpi::fadt().ok_or(PowerError::NoFadt)?;
+    let (slp_typa, slp_typb) = acpi::sleep_types(b"_S5_").ok_or(PowerError::NoSleepState)?;
+
+    let mut pm1a_control = pm1_control_port(fadt.pm1a_control_block, fadt.x_pm1a_control_block)?
+        .ok_or(PowerError::NoFadt)?;
+    let pm1b_control = pm1_control_port(fadt.pm1b_control_block, fadt.x_pm1b_control_block)?;
+
+    enable_acpi(&fadt, &mut pm1a_control)?;
+
+    unsafe {
+        pm1a_control.write((slp_typa as u16) << SLP_TYP_SHIFT | SLP_EN);
+        if let Some(mut pm1b_control) = pm1b_control {
+            pm1b_control.write((slp_typb as u16) << SLP_TYP_SHIFT | SLP_EN);
+        }
+    }
+
+    pit::delay(POWER_TIMEOUT);
+
+    Ok(())
+}
+
+/// Writes the reset value of the FADT to its reset register.
+fn acpi_reset() -> Result<(), PowerError> {
+    let fadt = acpi::fadt().ok_or(PowerError::NoFadt)?;
+    if !fadt.supports_reset() {
+        return Err(PowerError::NoResetRegister);
+    }
+
+    let register = fadt.reset_register;
+    match register.address_space {
+        GenericAddress::SYSTEM_IO => unsafe { Port::<u8>::new(register.address as u16).write(fadt.reset_value) },
+        GenericAddress::SYSTEM_MEMORY => unsafe {
+            let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE
+                | PageTableFlags::NO_EXECUTE;
+            let addr = paging::map_physical(PhysAddr::new(register.address), 1, flags)
+                .map_err(|_| PowerError::UnsupportedAddressSpace)?;
+            ptr::write_volatile(addr.as_mut_ptr::<u8>(), fadt.reset_value);
+        },
+        _ => return Err(PowerError::UnsupportedAddressSpace),
+    }
+
+    pit::delay(POWER_TIMEOUT);
+
+    Ok(())
+}
+
+/// Pulses the reset line of the processor through the output port of the 8042 keyboard controller.
+fn keyboard_controller_reset() {
+    let mut status: Port<u8> = Port::new(KBC_STATUS_PORT);
+
+    unsafe {
+        // Wait until the controller is ready to accept a command.
+        for _ in 0..0x10000 {
+            if status.read() & KBC_INPUT_FULL == 0 {
+                break;
+            }
+        }
+
+        status.write(KBC_PULSE_RESET);
+    }
+
+    pit::delay(POWER_TIMEOUT);
+}
+
+/// Raises an exception without a valid IDT, which escalates into a triple fault and resets the processor.
+fn triple_fault() -> ! {
+    let idt = DescriptorTablePointer { limit: 0, base: VirtAddr::new(0) };
+
+    unsafe {
+        lidt(&idt);
+        asm!("int3", options(nomem, nostack));
+    }
+
+    super::hlt_loop();
+}
+
+pub fn shutdown() -> ! {
+    instructions::interrupts::disable();
+    log::info!("power: shutting down");
+
+    // Failures are printed regardless of the log level, since they are the only explanation for the machine staying on.
+    match acpi_shutdown() {
+        Ok(()) => serial_println!("power: ACPI shutdown did not power off the machine"),
+        Err(err) => serial_println!("power: ACPI shutdown failed: {:?}", err),
+    }
+
+    serial_println!("power: halting instead");
+    super::hlt_loop();
+}
+
+pub fn reboot() -> ! {
+    instructions::interrupts::disable();
+    log::info!("power: rebooting");
+
+    match acpi_reset() {
+        Ok(()) => serial_println!("power: ACPI reset register did not reset the machine"),
+        Err(err) => serial_println!("power: ACPI reset failed: {:?}", err),
+    }
+
+    keyboard_controller_reset();
+    serial_println!("power: 8042 reset pulse did not reset the machine");
+
+    serial_println!("power: resetting through a triple fault");
+    triple_fault();
+}
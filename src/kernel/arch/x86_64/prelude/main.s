@@ -29,6 +29,11 @@
 
 
 .global _start
+.global _P4T
+.global _STACK_GUARD_PAGE
+.global _STACK_BOTTOM
+.global _STACK_TOP
+.global _KERNEL_WINDOW_SIZE
 
 
 .extern k_main
@@ -38,6 +43,10 @@
 
 .set _KERNEL_OFFSET, 0xFFFFFFFF80000000
 
+/* The low physical memory, including the kernel image, is mapped into the higher half through this many page tables. */
+.set _KERNEL_WINDOW_TABLES, 4
+.set _KERNEL_WINDOW_SIZE, _KERNEL_WINDOW_TABLES * 0x200000
+
 .set _STACK_SIZE, 16384
 .set _P_STACK_TOP, _STACK_TOP - _KERNEL_OFFSET
 
@@ -49,9 +58,12 @@
 .set _P_P2T_I, _P2T_I - _KERNEL_OFFSET
 .set _P_P2T_K, _P2T_K - _KERNEL_OFFSET
 
-.set _P_P1T_I, _P1T_I - _KERNEL_OFFSET
 .set _P_P1T_K, _P1T_K - _KERNEL_OFFSET
 
+/* The multiboot structure is copied into the kernel image if it fits, so that it is mapped wherever it was put. */
+.set _MULTIBOOT_COPY_SIZE, 0x10000
+.set _P_MULTIBOOT_COPY, _MULTIBOOT_COPY - _KERNEL_OFFSET
+
 /**
  * The Global Descriptor Table (GDT) is a structure that contains the segments of the program.
  *
@@ -87,6 +99,7 @@ _start:
 
     /* Perform necessary checks to ensure compatibility. */
     call _check_multiboot
+    call _copy_multiboot
     call _check_cpuid
     call _check_long_mode
 
@@ -111,6 +124,27 @@ _start:
     hlt
 
 
+/**
+ * Copies the multiboot structure at EDI into the kernel image and points EDI to the copy. The bootloader may put the
+ * structure anywhere in memory, whereas only the low physical memory is mapped into the higher half. A structure
+ * that is too large for the copy is left in place.
+ */
+_copy_multiboot:
+    /* The structure starts with its total size. */
+    mov ecx, dword ptr [edi]
+    cmp ecx, _MULTIBOOT_COPY_SIZE
+    ja ._copy_multiboot_done
+
+    cld
+    mov esi, edi
+    mov edi, offset _P_MULTIBOOT_COPY
+    rep movsb
+    mov edi, offset _P_MULTIBOOT_COPY
+
+._copy_multiboot_done:
+    ret
+
+
 /**
  * Checks if the kernel was loaded by multiboot compliant bootloader.
  */
@@ -189,32 +223,42 @@ _set_up_page_tables:
     lea eax, [_P_P3T_I]
     mov dword ptr [eax], ebx
 
-    lea ebx, [_P_P1T_I + 0x3]
-    lea eax, [_P_P2T_I]
-    mov dword ptr [eax], ebx
-
+    /* The identity mapping is only needed until the jump to the higher half, so 2 MiB pages suffice. */
     mov ecx, 0
-    mov eax, 0x3
+    mov eax, 0x83
 
 ._identity_map:
-    mov dword ptr [_P_P1T_I + ecx * 8], eax
-    add eax, 0x1000
+    mov dword ptr [_P_P2T_I + ecx * 8], eax
+    add eax, 0x200000
 
     inc ecx
-    cmp ecx, 512
+    cmp ecx, _KERNEL_WINDOW_TABLES
     jne ._identity_map
 
     lea ebx, [_P_P3T_K + 0x3]
     lea eax, [_P_P4T + 511 * 8]
     mov dword ptr [eax], ebx
 
+    /* Map the PML4 onto itself so that the page tables can be modified once paging is enabled. */
+    lea ebx, [_P_P4T + 0x3]
+    lea eax, [_P_P4T + 510 * 8]
+    mov dword ptr [eax], ebx
+
     lea ebx, [_P_P2T_K + 0x3]
     lea eax, [_P_P3T_K + 510 * 8]
     mov dword ptr [eax], ebx
 
-    lea ebx, [_P_P1T_K + 0x3]
-    lea eax, [_P_P2T_K]
-    mov dword ptr [eax], ebx
+    /* The page tables of the kernel window lie back to back, so they can be filled as if they were a single one. */
+    mov ecx, 0
+    lea eax, [_P_P1T_K + 0x3]
+
+._kernel_tables:
+    mov dword ptr [_P_P2T_K + ecx * 8], eax
+    add eax, 0x1000
+
+    inc ecx
+    cmp ecx, _KERNEL_WINDOW_TABLES
+    jne ._kernel_tables
 
     mov ecx, 0
     mov eax, 0x3
@@ -224,7 +268,7 @@ _set_up_page_tables:
     add eax, 0x1000
 
     inc ecx
-    cmp ecx, 512
+    cmp ecx, _KERNEL_WINDOW_TABLES * 512
     jne ._kernel_map
 
     ret
@@ -278,6 +322,7 @@ _start_higher_kernel:
     mov rax, _KERNEL_OFFSET
     or rsp, rax
 
+    /* Hand over the higher half alias of the multiboot structure, the identity mapping is torn down later on. */
     or rdi, rax
 
     xor rbp, rbp
@@ -308,10 +353,13 @@ _P2T_I:
     .space 4096
 _P2T_K:
     .space 4096
-_P1T_I:
-    .space 4096
 _P1T_K:
-    .space 4096
+    .space 4096 * _KERNEL_WINDOW_TABLES
+/* The multiboot structure and its tags are 8 byte aligned. */
+.align 8
+_MULTIBOOT_COPY:
+    .space _MULTIBOOT_COPY_SIZE
+.align 4096
 _STACK_GUARD_PAGE:
     .space 4096
 _STACK_BOTTOM:
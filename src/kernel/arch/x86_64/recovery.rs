@@ -0,0 +1,120 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use core::arch::global_asm;
+
+/// Recovery Point
+///
+/// The callee-saved registers, the stack pointer and RFLAGS of a call made through `call`. Resuming the recovery
+/// point from anywhere below that call, e.g. from an exception handler, makes `call` return early, much like
+/// `setjmp` and `longjmp` in C. Since the frames in between are abandoned, nothing they own is dropped and any lock
+/// they hold stays locked.
+#[derive(Debug)]
+#[repr(C)]
+pub struct RecoveryPoint {
+    rbx: u64,
+    rbp: u64,
+    r12: u64,
+    r13: u64,
+    r14: u64,
+    r15: u64,
+    rsp: u64,
+    rflags: u64,
+}
+
+global_asm!(
+    ".global asmos_recovery_call",
+    "asmos_recovery_call:",
+    "    mov [rdi + 0x00], rbx",
+    "    mov [rdi + 0x08], rbp",
+    "    mov [rdi + 0x10], r12",
+    "    mov [rdi + 0x18], r13",
+    "    mov [rdi + 0x20], r14",
+    "    mov [rdi + 0x28], r15",
+    "    mov [rdi + 0x30], rsp",
+    "    pushfq",
+    "    pop qword ptr [rdi + 0x38]",
+    // Keep the stack 16-byte aligned for the callee.
+    "    sub rsp, 8",
+    "    mov rdi, rdx",
+    "    call rsi",
+    "    add rsp, 8",
+    "    mov eax, 1",
+    "    ret",
+    "",
+    ".global asmos_recovery_resume",
+    "asmos_recovery_resume:",
+    "    mov rbx, [rdi + 0x00]",
+    "    mov rbp, [rdi + 0x08]",
+    "    mov r12, [rdi + 0x10]",
+    "    mov r13, [rdi + 0x18]",
+    "    mov r14, [rdi + 0x20]",
+    "    mov r15, [rdi + 0x28]",
+    "    mov rsp, [rdi + 0x30]",
+    "    push qword ptr [rdi + 0x38]",
+    "    popfq",
+    // Return from `asmos_recovery_call` as if the callee had not returned.
+    "    xor eax, eax",
+    "    ret",
+);
+
+extern "C" {
+    fn asmos_recovery_call(point: *mut RecoveryPoint, f: extern "C" fn(*mut u8), data: *mut u8) -> u64;
+    fn asmos_recovery_resume(point: *const RecoveryPoint) -> !;
+}
+
+impl Default for RecoveryPoint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+extern "C" fn trampoline<F: FnOnce()>(data: *mut u8) {
+    let f = unsafe { (*(data as *mut Option<F>)).take().unwrap() };
+    f();
+}
+
+impl RecoveryPoint {
+    pub const fn new() -> Self {
+        RecoveryPoint { rbx: 0, rbp: 0, r12: 0, r13: 0, r14: 0, r15: 0, rsp: 0, rflags: 0 }
+    }
+
+    /// Calls `f` and returns `true` if it returned, or `false` if the recovery point was resumed while it ran.
+    ///
+    /// # Safety
+    ///
+    /// The recovery point must stay in place until `call` returns.
+    pub unsafe fn call<F: FnOnce()>(point: *mut Self, f: F) -> bool {
+        let mut f = Some(f);
+
+        asmos_recovery_call(point, trampoline::<F>, &mut f as *mut Option<F> as *mut u8) != 0
+    }
+
+    /// Returns to the recovery point, i.e. makes its pending `call` return `false`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must be running below a pending `call` of the recovery point.
+    pub unsafe fn resume(point: *const Self) -> ! {
+        asmos_recovery_resume(point);
+    }
+}
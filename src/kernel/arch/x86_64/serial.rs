@@ -22,28 +22,258 @@
 
 use core::fmt::Arguments;
 use core::fmt::Write;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use lazy_static::lazy_static;
 use spin::Mutex;
-use uart_16550::SerialPort;
 use x86_64::instructions;
 
+use super::irq;
+use super::uart::{LineConfig, Uart};
+use crate::aux::ring_buffer::RingBuffer;
+use crate::kernel::cmdline::{self, SerialConfig};
+
+pub const PORT_COUNT: usize = 4;
+
+/// The I/O ports of COM1 to COM4; on x86_64 architecture, the UART serial devices are accessed through port-mapped
+/// I/O.
+const PORT_BASES: [u16; PORT_COUNT] = [0x3F8, 0x2F8, 0x3E8, 0x2E8];
+/// The ISA interrupt lines of COM1 to COM4, where COM1 and COM3 as well as COM2 and COM4 share a line.
+const PORT_IRQ_LINES: [u8; PORT_COUNT] = [4, 3, 4, 3];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialError {
+    NoSuchPort,
+    NotPresent,
+    InvalidConfig,
+}
+
+/// Serial Role
+///
+/// What a serial port is used for. Every role sits on exactly one port, while a port may serve several roles; in
+/// that case, its input must only be read through one of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Kernel messages, the logger and panic reports.
+    Log,
+    /// The interactive debug console.
+    Console,
+    /// The remote debugger.
+    Debugger,
+}
+
 lazy_static! {
-    /// Serial communication through 16550 UART interface.
-    static ref UART_3F8: Mutex<SerialPort> = {
-        // On x86_64 architecture, the UART serial device is accessed through port-mapped I/O.
-        const SERIAL_IO_PORT: u16 = 0x3F8;
+    /// Serial Port Registry
+    ///
+    /// The UARTs that answered the loopback test, indexed from COM1 to COM4.
+    static ref PORTS: [Mutex<Option<Uart>>; PORT_COUNT] = core::array::from_fn(|index| {
+        let mut port = unsafe { Uart::new(PORT_BASES[index]) };
+
+        Mutex::new(port.probe(&LineConfig::default()).ok().map(|_| port))
+    });
+}
+
+/// The port of every role, all of which start out on COM1.
+static ROLES: [AtomicUsize; 3] = [AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0)];
+
+/// Bytes that were received on every port, but not read yet.
+static INPUTS: [RingBuffer<256>; PORT_COUNT] = [
+    RingBuffer::new(), RingBuffer::new(), RingBuffer::new(), RingBuffer::new(),
+];
+
+/// Returns the index of the port that the given role sits on, counted from 0 for COM1.
+pub fn port_of(role: Role) -> usize {
+    ROLES[role as usize].load(Ordering::Relaxed)
+}
 
-        let mut port = unsafe { SerialPort::new(SERIAL_IO_PORT) };
-        port.init();
+/// Returns `true` if the port with the given index exists.
+pub fn is_present(port: usize) -> bool {
+    port < PORT_COUNT && instructions::interrupts::without_interrupts(|| PORTS[port].lock().is_some())
+}
+
+/// Moves the given role to the port with the given index.
+pub fn assign(role: Role, port: usize) -> Result<(), SerialError> {
+    if port >= PORT_COUNT {
+        return Err(SerialError::NoSuchPort);
+    }
+    if !is_present(port) {
+        return Err(SerialError::NotPresent);
+    }
 
-        Mutex::new(port)
-    };
+    ROLES[role as usize].store(port, Ordering::Relaxed);
+
+    Ok(())
+}
+
+/// Changes the baud rate and framing of the port with the given index.
+pub fn configure(port: usize, config: &LineConfig) -> Result<(), SerialError> {
+    if port >= PORT_COUNT {
+        return Err(SerialError::NoSuchPort);
+    }
+
+    instructions::interrupts::without_interrupts(|| {
+        let mut uart = PORTS[port].lock();
+        let uart = uart.as_mut().ok_or(SerialError::NotPresent)?;
+
+        uart.configure(config).map_err(|_| SerialError::InvalidConfig)?;
+        uart.enable_receive_interrupt();
+
+        Ok(())
+    })
+}
+
+/// Prints to the port of the given role; output to a port that does not exist is dropped.
+pub fn write_fmt(role: Role, args: Arguments) {
+    instructions::interrupts::without_interrupts(|| {
+        if let Some(uart) = PORTS[port_of(role)].lock().as_mut() {
+            uart.write_fmt(args).expect("failed to print to serial output");
+        }
+    });
+}
+
+/// Sends the given bytes as they are to the port of the given role.
+pub fn write_bytes(role: Role, bytes: &[u8]) {
+    instructions::interrupts::without_interrupts(|| {
+        if let Some(uart) = PORTS[port_of(role)].lock().as_mut() {
+            bytes.iter().for_each(|&byte| uart.send_raw(byte));
+        }
+    });
 }
 
 #[doc(hidden)]
 pub fn _print(args: Arguments) {
-    instructions::interrupts::without_interrupts(
-        || { UART_3F8.lock().write_fmt(args).expect("failed to print to serial output"); }
-    );
+    write_fmt(Role::Log, args);
+}
+
+/// Releases the locks on the serial ports, regardless of who holds them.
+///
+/// # Safety
+///
+/// The holders of the locks must never run again, e.g. because the kernel panicked while it was printing.
+pub unsafe fn force_unlock() {
+    for port in PORTS.iter() {
+        if port.is_locked() {
+            port.force_unlock();
+        }
+    }
+}
+
+/// Moves every byte out of the receive FIFO of the given port into its input buffer.
+fn on_receive(port: usize) {
+    // Printing masks interrupts while it holds the lock, so it is free whenever this handler runs.
+    if let Some(uart) = PORTS[port].lock().as_mut() {
+        while let Some(byte) = uart.try_receive() {
+            // Bytes that do not fit anymore are dropped, the UART has to be drained either way.
+            INPUTS[port].push(byte);
+        }
+    }
+}
+
+/// Returns the next byte that was received on the port of the given role, if there is any.
+pub fn try_read_byte(role: Role) -> Option<u8> {
+    INPUTS[port_of(role)].pop()
+}
+
+/// Waits for the next byte on the port of the given role; interrupts must be enabled, otherwise it never arrives.
+pub fn read_byte(role: Role) -> u8 {
+    loop {
+        if let Some(byte) = try_read_byte(role) {
+            return byte;
+        }
+
+        // A byte that arrives between the check and the halt must still wake the processor up.
+        instructions::interrupts::disable();
+        if INPUTS[port_of(role)].is_empty() {
+            instructions::interrupts::enable_and_hlt();
+        } else {
+            instructions::interrupts::enable();
+        }
+    }
+}
+
+/// Waits for the next byte on the port of the given role by polling the UART, for code that runs with interrupts
+/// disabled, e.g. the GDB stub.
+pub fn poll_byte(role: Role) -> u8 {
+    let port = port_of(role);
+
+    loop {
+        if let Some(byte) = INPUTS[port].pop() {
+            return byte;
+        }
+        if let Some(byte) = PORTS[port].lock().as_mut().and_then(|uart| uart.try_receive()) {
+            return byte;
+        }
+
+        core::hint::spin_loop();
+    }
+}
+
+pub fn init() -> Result<(), ()> {
+    for port in 0..PORT_COUNT {
+        let present = instructions::interrupts::without_interrupts(|| match PORTS[port].lock().as_mut() {
+            Some(uart) => {
+                uart.enable_receive_interrupt();
+                true
+            }
+            None => false,
+        });
+        if !present {
+            continue;
+        }
+
+        let line = PORT_IRQ_LINES[port];
+        irq::register(irq::legacy_vector(line), move |_| on_receive(port)).map_err(|_| ())?;
+        irq::unmask(line);
+
+        log::info!("serial: COM{} at {:#X}, IRQ {}", port + 1, PORT_BASES[port], line);
+    }
+
+    // A port that is missing or rejects its settings must not keep the kernel from booting.
+    if let Some(serial) = cmdline::config().serial {
+        match move_log_and_console(&serial) {
+            Ok(()) => log::info!("serial: log and console on COM{}", serial.port + 1),
+            Err(err) => {
+                let current = port_of(Role::Log) + 1;
+                log::warn!("serial: cannot use COM{} ({:?}), keeping COM{}", serial.port + 1, err, current);
+            }
+        }
+    }
+
+    if let Some(port) = cmdline::config().gdb {
+        match assign(Role::Debugger, port) {
+            Ok(()) => log::info!("serial: debugger on COM{}", port + 1),
+            Err(err) => log::warn!("serial: cannot use COM{} for the debugger ({:?})", port + 1, err),
+        }
+    }
+
+    Ok(())
+}
+
+fn move_log_and_console(serial: &SerialConfig) -> Result<(), SerialError> {
+    if !is_present(serial.port) {
+        return Err(SerialError::NotPresent);
+    }
+    if let Some(line) = &serial.line {
+        configure(serial.port, line)?;
+    }
+
+    assign(Role::Log, serial.port)?;
+    assign(Role::Console, serial.port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn com1_is_present() {
+        assert!(is_present(0));
+        assert_eq!(port_of(Role::Log), 0);
+    }
+
+    #[test_case]
+    fn roles_stay_on_existing_ports() {
+        assert_eq!(assign(Role::Debugger, PORT_COUNT), Err(SerialError::NoSuchPort));
+        assert_eq!(assign(Role::Debugger, 0), Ok(()));
+    }
 }
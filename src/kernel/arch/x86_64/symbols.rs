@@ -0,0 +1,111 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use core::mem;
+use core::ops::Range;
+
+use spin::Once;
+use x86_64::PhysAddr;
+use x86_64::structures::paging::PageTableFlags;
+
+use super::{elf, paging};
+
+/// ELF Symbol Table Entry (Elf64_Sym)
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Symbol {
+    name: u32,
+    info: u8,
+    other: u8,
+    section_index: u16,
+    value: u64,
+    size: u64,
+}
+
+impl Symbol {
+    const TYPE_FUNC: u8 = 2;
+
+    fn is_function(&self) -> bool {
+        self.info & 0xF == Self::TYPE_FUNC
+    }
+}
+
+/// Kernel Symbol Table
+///
+/// The `.symtab` and `.strtab` sections of the kernel, which are not part of the loaded image but are copied into
+/// memory by the bootloader and described by the ELF sections tag of the boot information.
+struct SymbolTable {
+    symbols: &'static [Symbol],
+    strings: &'static [u8],
+}
+
+impl SymbolTable {
+    unsafe fn map(symtab: Range<usize>, strtab: Range<usize>) -> Option<Self> {
+        let flags = PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE;
+
+        let symbols_addr = paging::map_physical(PhysAddr::new(symtab.start as u64), symtab.len(), flags).ok()?;
+        let strings_addr = paging::map_physical(PhysAddr::new(strtab.start as u64), strtab.len(), flags).ok()?;
+
+        Some(SymbolTable {
+            symbols: core::slice::from_raw_parts(symbols_addr.as_ptr(), symtab.len() / mem::size_of::<Symbol>()),
+            strings: core::slice::from_raw_parts(strings_addr.as_ptr(), strtab.len()),
+        })
+    }
+
+    fn name(&self, symbol: &Symbol) -> Option<&'static str> {
+        let strings = self.strings.get(symbol.name as usize..)?;
+        let length = strings.iter().position(|&byte| byte == 0)?;
+
+        core::str::from_utf8(&strings[..length]).ok()
+    }
+
+    fn resolve(&self, addr: u64) -> Option<(&'static str, u64)> {
+        let symbol = self.symbols.iter().find(|symbol| {
+            symbol.is_function() && (symbol.value..symbol.value + symbol.size.max(1)).contains(&addr)
+        })?;
+
+        Some((self.name(symbol)?, addr - symbol.value))
+    }
+}
+
+static SYMBOL_TABLE: Once<Option<SymbolTable>> = Once::new();
+
+/// Returns the (mangled) name of the function that contains the given address, along with the offset into it.
+pub fn resolve(addr: u64) -> Option<(&'static str, u64)> {
+    SYMBOL_TABLE.get()?.as_ref()?.resolve(addr)
+}
+
+pub fn init() -> Result<(), ()> {
+    let symbol_table = match (elf::symbol_table_region(), elf::string_table_region()) {
+        (Some(symtab), Some(strtab)) => unsafe { SymbolTable::map(symtab, strtab) },
+        _ => None,
+    };
+
+    match &symbol_table {
+        Some(symbol_table) => log::info!("symbols: {} entries", symbol_table.symbols.len()),
+        None => log::warn!("symbols: no symbol table, stack traces will not be symbolized"),
+    }
+
+    SYMBOL_TABLE.call_once(|| symbol_table);
+
+    Ok(())
+}
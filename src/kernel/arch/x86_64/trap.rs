@@ -0,0 +1,224 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use core::arch::global_asm;
+
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::VirtAddr;
+
+use super::cpu::{CpuState, GeneralRegisters};
+use super::exceptions::*;
+
+/// Trap Frame
+///
+/// Everything that the processor and the entry stubs save when an exception is taken, from the lowest address up.
+/// Unlike the `x86-interrupt` ABI, the stubs save every general purpose register before any compiled code runs, so
+/// that a debugger can inspect and modify the state of the interrupted code, and a fatal exception can report it.
+#[derive(Debug)]
+#[repr(C)]
+pub struct TrapFrame {
+    pub registers: GeneralRegisters,
+    pub vector: u64,
+    /// The error code pushed by the processor, or zero for the vectors that do not have one.
+    pub error_code: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+impl TrapFrame {
+    /// Returns the interrupt frame that the processor pushed.
+    pub fn stack_frame(&self) -> &InterruptStackFrame {
+        // The five words from RIP to SS are laid out exactly like an `InterruptStackFrame`.
+        unsafe { &*(&self.rip as *const u64 as *const InterruptStackFrame) }
+    }
+
+    /// Returns the error code, if the processor pushes one for the vector.
+    pub fn error_code(&self) -> Option<u64> {
+        has_error_code(self.vector as u8).then_some(self.error_code)
+    }
+
+    /// Takes a snapshot of the interrupted code.
+    pub fn cpu_state(&self) -> CpuState {
+        CpuState::from_exception(self.stack_frame(), self.vector as u8, self.error_code(), self.registers)
+    }
+}
+
+fn has_error_code(vector: u8) -> bool {
+    matches!(vector, 0x08 | 0x0A..=0x0E | 0x11 | 0x15 | 0x1D | 0x1E)
+}
+
+// Every exception vector gets a stub that pushes a zero in place of a missing error code and then the vector, so
+// that all of them share the same frame layout. `asmos_trap_entries` lists the stubs by vector.
+global_asm!(
+    ".macro asmos_trap_entry vector, error_code",
+    "asmos_trap_entry_\\vector:",
+    ".if \\error_code == 0",
+    "    push 0",
+    ".endif",
+    "    push \\vector",
+    "    jmp asmos_trap_common",
+    ".endm",
+    "",
+    ".irp vector, 0,1,2,3,4,5,6,7,9,15,16,18,19,20,22,23,24,25,26,27,28,31",
+    "    asmos_trap_entry \\vector, 0",
+    ".endr",
+    ".irp vector, 8,10,11,12,13,14,17,21,29,30",
+    "    asmos_trap_entry \\vector, 1",
+    ".endr",
+    "",
+    "asmos_trap_common:",
+    "    push rax",
+    "    push rbx",
+    "    push rcx",
+    "    push rdx",
+    "    push rsi",
+    "    push rdi",
+    "    push rbp",
+    "    push r8",
+    "    push r9",
+    "    push r10",
+    "    push r11",
+    "    push r12",
+    "    push r13",
+    "    push r14",
+    "    push r15",
+    "    mov rdi, rsp",
+    "    cld",
+    // The processor aligns the stack before it pushes the five words of the interrupt frame, and the error code, the
+    // vector and the fifteen registers keep it 16-byte aligned.
+    "    call {dispatch}",
+    "    pop r15",
+    "    pop r14",
+    "    pop r13",
+    "    pop r12",
+    "    pop r11",
+    "    pop r10",
+    "    pop r9",
+    "    pop r8",
+    "    pop rbp",
+    "    pop rdi",
+    "    pop rsi",
+    "    pop rdx",
+    "    pop rcx",
+    "    pop rbx",
+    "    pop rax",
+    "    add rsp, 16",
+    "    iretq",
+    "",
+    ".pushsection .rodata",
+    ".balign 8",
+    ".global asmos_trap_entries",
+    "asmos_trap_entries:",
+    ".irp vector, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+    "    .quad asmos_trap_entry_\\vector",
+    ".endr",
+    ".popsection",
+    dispatch = sym dispatch,
+);
+
+extern "C" {
+    static asmos_trap_entries: [u64; 32];
+}
+
+extern "C" fn dispatch(frame: &mut TrapFrame) {
+    match frame.vector as u8 {
+        DivisionErrorException::CODE => DivisionErrorException::handler(frame),
+        DebugException::CODE => DebugException::handler(frame),
+        BreakpointException::CODE => BreakpointException::handler(frame),
+        OverflowException::CODE => OverflowException::handler(frame),
+        BoundRangeExceededException::CODE => BoundRangeExceededException::handler(frame),
+        InvalidOpcodeException::CODE => InvalidOpcodeException::handler(frame),
+        DeviceNotAvailableException::CODE => DeviceNotAvailableException::handler(frame),
+        DoubleFaultException::CODE => DoubleFaultException::handler(frame),
+        InvalidTSSException::CODE => InvalidTSSException::handler(frame),
+        SegmentNotPresentException::CODE => SegmentNotPresentException::handler(frame),
+        StackSegmentFaultException::CODE => StackSegmentFaultException::handler(frame),
+        GeneralProtectionFaultException::CODE => GeneralProtectionFaultException::handler(frame),
+        PageFaultException::CODE => PageFaultException::handler(frame),
+        X87FloatingPointException::CODE => X87FloatingPointException::handler(frame),
+        AlignmentCheckException::CODE => AlignmentCheckException::handler(frame),
+        MachineCheckException::CODE => MachineCheckException::handler(frame),
+        SIMDFloatingPointException::CODE => SIMDFloatingPointException::handler(frame),
+        VirtualizationException::CODE => VirtualizationException::handler(frame),
+        ControlProtectionException::CODE => ControlProtectionException::handler(frame),
+        HypervisorInjectionException::CODE => HypervisorInjectionException::handler(frame),
+        VMMCommunicationException::CODE => VMMCommunicationException::handler(frame),
+        SecurityException::CODE => SecurityException::handler(frame),
+        vector => unreachable!("no entry stub is installed for vector {:#04X}", vector),
+    }
+}
+
+/// Returns the entry stub of the given exception vector, to be installed in the IDT.
+pub fn entry(vector: u8) -> VirtAddr {
+    VirtAddr::new(unsafe { asmos_trap_entries[vector as usize] })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(vector: u8, error_code: u64) -> TrapFrame {
+        TrapFrame {
+            registers: GeneralRegisters { rbp: 0xFFFF_8000_0010_0000, ..GeneralRegisters::default() },
+            vector: vector as u64,
+            error_code,
+            rip: 0xFFFF_8000_0000_1234,
+            cs: 0x08,
+            rflags: 0x202,
+            rsp: 0xFFFF_8000_0020_0000,
+            ss: 0x10,
+        }
+    }
+
+    #[test_case]
+    fn stack_frame_overlays_the_saved_words() {
+        let frame = frame(InvalidOpcodeException::CODE, 0);
+        let stack_frame = frame.stack_frame();
+
+        assert_eq!(stack_frame.instruction_pointer.as_u64(), frame.rip);
+        assert_eq!(stack_frame.code_segment, frame.cs);
+        assert_eq!(stack_frame.cpu_flags, frame.rflags);
+        assert_eq!(stack_frame.stack_pointer.as_u64(), frame.rsp);
+        assert_eq!(stack_frame.stack_segment, frame.ss);
+    }
+
+    #[test_case]
+    fn cpu_state_keeps_the_registers() {
+        let state = frame(GeneralProtectionFaultException::CODE, 0x18).cpu_state();
+
+        assert_eq!(state.vector, Some(GeneralProtectionFaultException::CODE));
+        assert_eq!(state.error_code, Some(0x18));
+        assert_eq!(state.registers.map(|registers| registers.rbp), Some(0xFFFF_8000_0010_0000));
+
+        assert_eq!(frame(InvalidOpcodeException::CODE, 0).cpu_state().error_code, None);
+    }
+
+    #[test_case]
+    fn every_vector_has_an_entry() {
+        for vector in 0..32 {
+            assert!(!entry(vector).is_null());
+        }
+    }
+}
@@ -0,0 +1,247 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use core::fmt;
+
+use x86_64::instructions::port::Port;
+
+/// The frequency of the UART clock divided by 16, which is the highest baud rate it supports.
+const BASE_BAUD_RATE: u32 = 115_200;
+
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+const IER_DATA_AVAILABLE: u8 = 1 << 0;
+const FCR_ENABLE_AND_CLEAR_14_BYTES: u8 = 0xC7;
+const LCR_DIVISOR_LATCH: u8 = 1 << 7;
+const MCR_DTR_RTS_OUT2: u8 = 0x0B;
+const MCR_LOOPBACK: u8 = 0x1E;
+const MCR_NORMAL: u8 = 0x0F;
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_TRANSMITTER_EMPTY: u8 = 1 << 5;
+
+/// The byte that is sent through the loopback while probing.
+const PROBE_BYTE: u8 = 0xAE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UartError {
+    NotPresent,
+    InvalidBaudRate,
+    InvalidDataBits,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+    Mark,
+    Space,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+/// Line Configuration
+///
+/// The framing of every character on the line; both ends have to agree on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineConfig {
+    pub baud_rate: u32,
+    pub data_bits: u8,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl LineConfig {
+    fn divisor(&self) -> Result<u16, UartError> {
+        if self.baud_rate == 0 || !BASE_BAUD_RATE.is_multiple_of(self.baud_rate) {
+            return Err(UartError::InvalidBaudRate);
+        }
+
+        // The divisor latch is only 16 bits wide, which rules out rates below 2 baud.
+        u16::try_from(BASE_BAUD_RATE / self.baud_rate).map_err(|_| UartError::InvalidBaudRate)
+    }
+
+    fn line_control(&self) -> Result<u8, UartError> {
+        if !(5..=8).contains(&self.data_bits) {
+            return Err(UartError::InvalidDataBits);
+        }
+
+        let parity = match self.parity {
+            Parity::None => 0b000,
+            Parity::Odd => 0b001,
+            Parity::Even => 0b011,
+            Parity::Mark => 0b101,
+            Parity::Space => 0b111,
+        };
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 0,
+            StopBits::Two => 1,
+        };
+
+        Ok((self.data_bits - 5) | stop_bits << 2 | parity << 3)
+    }
+}
+
+impl Default for LineConfig {
+    /// 38400 baud, 8 data bits, no parity and one stop bit.
+    fn default() -> Self {
+        LineConfig { baud_rate: 38_400, data_bits: 8, parity: Parity::None, stop_bits: StopBits::One }
+    }
+}
+
+/// Universal Asynchronous Receiver-Transmitter (16550 UART)
+///
+/// The serial port of the PC, which is accessed through eight consecutive I/O ports.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/Serial_Ports
+pub struct Uart {
+    base: u16,
+}
+
+impl Uart {
+    /// # Safety
+    ///
+    /// The given I/O port must be the base of a UART that is not driven by anyone else.
+    pub const unsafe fn new(base: u16) -> Self {
+        Uart { base }
+    }
+
+    pub fn base(&self) -> u16 {
+        self.base
+    }
+
+    fn read(&self, register: u16) -> u8 {
+        unsafe { Port::new(self.base + register).read() }
+    }
+
+    fn write(&mut self, register: u16, value: u8) {
+        unsafe { Port::new(self.base + register).write(value); }
+    }
+
+    /// Configures the line and checks through the loopback mode that the UART actually exists.
+    pub fn probe(&mut self, config: &LineConfig) -> Result<(), UartError> {
+        self.configure(config)?;
+
+        // In loopback mode, every byte that is sent is received right away, without reaching the line.
+        self.write(MODEM_CONTROL, MCR_LOOPBACK);
+        self.write(DATA, PROBE_BYTE);
+        if self.read(DATA) != PROBE_BYTE {
+            return Err(UartError::NotPresent);
+        }
+
+        self.write(MODEM_CONTROL, MCR_NORMAL);
+
+        Ok(())
+    }
+
+    /// Sets the baud rate and framing of the line; interrupts are disabled until they are enabled again.
+    pub fn configure(&mut self, config: &LineConfig) -> Result<(), UartError> {
+        let divisor = config.divisor()?;
+        let line_control = config.line_control()?;
+
+        self.write(INTERRUPT_ENABLE, 0);
+
+        // The divisor shares its registers with the data and interrupt enable registers.
+        self.write(LINE_CONTROL, LCR_DIVISOR_LATCH);
+        self.write(DATA, divisor as u8);
+        self.write(INTERRUPT_ENABLE, (divisor >> 8) as u8);
+        self.write(LINE_CONTROL, line_control);
+
+        self.write(FIFO_CONTROL, FCR_ENABLE_AND_CLEAR_14_BYTES);
+        self.write(MODEM_CONTROL, MCR_DTR_RTS_OUT2);
+
+        Ok(())
+    }
+
+    /// Lets the UART raise an interrupt whenever a byte was received.
+    pub fn enable_receive_interrupt(&mut self) {
+        self.write(INTERRUPT_ENABLE, IER_DATA_AVAILABLE);
+    }
+
+    /// Sends a byte as is, once the transmitter is ready for it.
+    pub fn send_raw(&mut self, byte: u8) {
+        while self.read(LINE_STATUS) & LSR_TRANSMITTER_EMPTY == 0 {
+            core::hint::spin_loop();
+        }
+
+        self.write(DATA, byte);
+    }
+
+    /// Sends a byte, erasing the previous character on the terminal for a backspace or delete.
+    pub fn send(&mut self, byte: u8) {
+        match byte {
+            0x08 | 0x7F => {
+                self.send_raw(0x08);
+                self.send_raw(b' ');
+                self.send_raw(0x08);
+            }
+            _ => self.send_raw(byte),
+        }
+    }
+
+    /// Returns the next received byte, if there is any.
+    pub fn try_receive(&mut self) -> Option<u8> {
+        if self.read(LINE_STATUS) & LSR_DATA_READY == 0 {
+            return None;
+        }
+
+        Some(self.read(DATA))
+    }
+}
+
+impl fmt::Write for Uart {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.send(byte);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn line_control_encoding() {
+        let config = LineConfig { data_bits: 7, parity: Parity::Even, stop_bits: StopBits::Two, ..Default::default() };
+        assert_eq!(config.line_control(), Ok(0b0001_1110));
+        assert_eq!(LineConfig::default().line_control(), Ok(0b0000_0011));
+    }
+
+    #[test_case]
+    fn baud_rate_must_divide_the_clock() {
+        assert_eq!(LineConfig { baud_rate: 9600, ..Default::default() }.divisor(), Ok(12));
+        assert_eq!(LineConfig { baud_rate: 10_000, ..Default::default() }.divisor(), Err(UartError::InvalidBaudRate));
+        assert_eq!(LineConfig { baud_rate: 1, ..Default::default() }.divisor(), Err(UartError::InvalidBaudRate));
+    }
+}
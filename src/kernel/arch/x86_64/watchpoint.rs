@@ -0,0 +1,213 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use core::arch::asm;
+
+use spin::Mutex;
+use x86_64::registers::debug::{
+    BreakpointCondition, BreakpointSize, DebugAddressRegister, DebugAddressRegisterNumber, Dr0, Dr1, Dr2, Dr3, Dr6,
+    Dr6Flags, Dr7, Dr7Flags,
+};
+use x86_64::VirtAddr;
+
+pub const WATCHPOINT_COUNT: usize = 4;
+
+/// The value of DR6 without any debug condition, which has to be restored by the #DB handler.
+const DR6_CLEAR: u64 = 0xFFFF_0FF0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchpointError {
+    NoFreeSlot,
+    InvalidSlot,
+    InvalidLength,
+    Misaligned,
+}
+
+/// The access that triggers a watchpoint; the processor cannot watch reads alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Execute,
+    Write,
+    ReadWrite,
+}
+
+/// Watchpoint
+///
+/// An address range of 1, 2, 4 or 8 bytes that raises #DB whenever it is accessed in the given way. Execute
+/// watchpoints are faults, which are reported before the instruction runs, while data watchpoints are traps, which
+/// are reported right after the access.
+///
+/// OS Dev Wiki: https://wiki.osdev.org/CPU_Registers_x86-64#Debug_Registers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addr: VirtAddr,
+    pub kind: WatchKind,
+    pub len: usize,
+}
+
+static WATCHPOINTS: Mutex<[Option<Watchpoint>; WATCHPOINT_COUNT]> = Mutex::new([None; WATCHPOINT_COUNT]);
+
+fn register_number(slot: usize) -> DebugAddressRegisterNumber {
+    DebugAddressRegisterNumber::new(slot as u8).unwrap()
+}
+
+fn write_address(slot: usize, addr: u64) {
+    match slot {
+        0 => Dr0::write(addr),
+        1 => Dr1::write(addr),
+        2 => Dr2::write(addr),
+        _ => Dr3::write(addr),
+    }
+}
+
+/// Arms a free debug register and returns its slot.
+pub fn set(addr: VirtAddr, kind: WatchKind, len: usize) -> Result<usize, WatchpointError> {
+    let size = BreakpointSize::new(len).ok_or(WatchpointError::InvalidLength)?;
+    if kind == WatchKind::Execute && len != 1 {
+        return Err(WatchpointError::InvalidLength);
+    }
+    if !addr.is_aligned(len as u64) {
+        return Err(WatchpointError::Misaligned);
+    }
+
+    let condition = match kind {
+        WatchKind::Execute => BreakpointCondition::InstructionExecution,
+        WatchKind::Write => BreakpointCondition::DataWrites,
+        WatchKind::ReadWrite => BreakpointCondition::DataReadsWrites,
+    };
+
+    let mut watchpoints = WATCHPOINTS.lock();
+    let slot = watchpoints.iter().position(Option::is_none).ok_or(WatchpointError::NoFreeSlot)?;
+    let number = register_number(slot);
+
+    write_address(slot, addr.as_u64());
+
+    let mut dr7 = Dr7::read();
+    dr7.set_condition(number, condition);
+    dr7.set_size(number, size);
+    dr7.insert_flags(Dr7Flags::global_breakpoint_enable(number));
+    Dr7::write(dr7);
+
+    watchpoints[slot] = Some(Watchpoint { addr, kind, len });
+
+    Ok(slot)
+}
+
+/// Disarms the debug register of the given slot, which must be in use.
+pub fn clear(slot: usize) -> Result<(), WatchpointError> {
+    let mut watchpoints = WATCHPOINTS.lock();
+    let watchpoint = watchpoints.get_mut(slot).filter(|watchpoint| watchpoint.is_some());
+    let watchpoint = watchpoint.ok_or(WatchpointError::InvalidSlot)?;
+
+    let mut dr7 = Dr7::read();
+    dr7.remove_flags(Dr7Flags::global_breakpoint_enable(register_number(slot)));
+    Dr7::write(dr7);
+    write_address(slot, 0);
+
+    *watchpoint = None;
+
+    Ok(())
+}
+
+/// Disarms the watchpoint that watches exactly the given address in the given way, if there is one.
+pub fn clear_address(addr: VirtAddr, kind: WatchKind) -> Result<(), WatchpointError> {
+    let watchpoints = watchpoints();
+    let slot = watchpoints.iter().position(|watchpoint| {
+        watchpoint.is_some_and(|watchpoint| watchpoint.addr == addr && watchpoint.kind == kind)
+    });
+
+    clear(slot.ok_or(WatchpointError::InvalidSlot)?)
+}
+
+pub fn get(slot: usize) -> Option<Watchpoint> {
+    WATCHPOINTS.lock().get(slot).copied().flatten()
+}
+
+pub fn watchpoints() -> [Option<Watchpoint>; WATCHPOINT_COUNT] {
+    *WATCHPOINTS.lock()
+}
+
+/// Debug Status
+///
+/// The conditions that raised the last #DB, as reported in DR6.
+#[derive(Debug, Clone, Copy)]
+pub struct DebugStatus(Dr6Flags);
+
+impl DebugStatus {
+    /// Returns the slots of the watchpoints that fired.
+    pub fn triggered(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..WATCHPOINT_COUNT).filter(|&slot| self.0.contains(Dr6Flags::trap(register_number(slot))))
+    }
+
+    pub fn is_single_step(&self) -> bool {
+        self.0.contains(Dr6Flags::STEP)
+    }
+}
+
+/// Reads DR6 and clears it, since the processor never clears it on its own.
+pub fn take_status() -> DebugStatus {
+    let status = DebugStatus(Dr6::read());
+
+    unsafe { asm!("mov dr6, {}", in(reg) DR6_CLEAR, options(nomem, nostack, preserves_flags)); }
+
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use core::ptr;
+
+    use super::*;
+    use crate::kernel::arch::exceptions::DebugException;
+    use crate::kernel::recovery::{self, Event};
+
+    #[test_case]
+    fn write_watchpoint_fires() {
+        static mut WATCHED: u64 = 0;
+        let addr = VirtAddr::from_ptr(ptr::addr_of!(WATCHED));
+
+        let slot = set(addr, WatchKind::Write, 8).unwrap();
+        assert_eq!(recovery::catch(|| unsafe { ptr::write_volatile(ptr::addr_of_mut!(WATCHED), 1) }), None);
+        clear(slot).unwrap();
+
+        let event = Event::Exception { vector: DebugException::CODE, error_code: None };
+        assert_eq!(recovery::take_event(), Some(event));
+    }
+
+    #[test_case]
+    fn watchpoints_must_be_aligned() {
+        assert_eq!(set(VirtAddr::new(0x1001), WatchKind::Write, 4), Err(WatchpointError::Misaligned));
+        assert_eq!(set(VirtAddr::new(0x1000), WatchKind::Execute, 4), Err(WatchpointError::InvalidLength));
+    }
+
+    #[test_case]
+    fn only_armed_watchpoints_are_cleared() {
+        let addr = VirtAddr::new(0x1000);
+        let slot = set(addr, WatchKind::Execute, 1).unwrap();
+
+        assert_eq!(clear_address(addr, WatchKind::Write), Err(WatchpointError::InvalidSlot));
+        assert_eq!(get(slot).map(|watchpoint| watchpoint.kind), Some(WatchKind::Execute));
+
+        clear_address(addr, WatchKind::Execute).unwrap();
+        assert_eq!(clear(slot), Err(WatchpointError::InvalidSlot));
+    }
+}
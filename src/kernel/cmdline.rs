@@ -0,0 +1,270 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use log::LevelFilter;
+use spin::Once;
+
+use crate::aux;
+use crate::aux::log::MAX_FILTERS;
+
+use super::serial::{LineConfig, Parity, StopBits, PORT_COUNT};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmdlineError {
+    InvalidValue(&'static str),
+}
+
+/// The serial port that the log and the console are moved to, and its line settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialConfig {
+    pub port: usize,
+    pub line: Option<LineConfig>,
+}
+
+/// Kernel Configuration
+///
+/// The settings that were passed on the kernel command line, e.g. `loglevel=info,apic:trace serial=com2 noapic`.
+/// Settings that are not given keep their defaults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    /// `loglevel=<level>[,<target>:<level>...]` with `<level>` one of `off|error|warn|info|debug|trace`: the most
+    /// verbose messages that are logged, overall and for the modules whose path contains the target as whole segments.
+    pub log_level: LevelFilter,
+    pub log_filters: [Option<(&'static str, LevelFilter)>; MAX_FILTERS],
+    /// `serial=com<1-4>[,<baud>[<n|o|e|m|s><5-8>[<1|2>]]]`: where the log and the console go.
+    pub serial: Option<SerialConfig>,
+    /// `gdb=com<1-4>`: the port on which the GDB stub talks to the debugger, instead of the one of the console.
+    pub gdb: Option<usize>,
+    /// `test=<filter>`: only the tests whose name contains the filter are run.
+    pub test: Option<&'static str>,
+    /// `noapic`: interrupts are delivered through the legacy PIC, even if an APIC is available.
+    pub apic: bool,
+    /// `exit_on_boot`: the kernel reports its result through QEMU's `isa-debug-exit` device once it has booted or
+    /// panicked, instead of dropping into the monitor or halting.
+    pub exit_on_boot: bool,
+}
+
+impl Config {
+    pub const DEFAULT: Config = Config {
+        log_level: LevelFilter::Trace,
+        log_filters: [None; MAX_FILTERS],
+        serial: None,
+        gdb: None,
+        test: None,
+        apic: true,
+        exit_on_boot: false,
+    };
+}
+
+static CONFIG: Once<Config> = Once::new();
+
+/// Parses every entry before any of them is applied, so that an invalid entry leaves the whole setting untouched.
+fn parse_log_level(value: &'static str, config: &mut Config) -> Result<(), CmdlineError> {
+    let error = CmdlineError::InvalidValue("loglevel");
+    let mut log_level = config.log_level;
+    let mut log_filters = config.log_filters;
+
+    for setting in value.split(',') {
+        match setting.split_once(':') {
+            Some((target, level)) if !target.is_empty() => {
+                let level = level.parse().map_err(|_| error)?;
+                let slot = log_filters.iter_mut()
+                                      .find(|filter| filter.is_none_or(|(other, _)| other == target))
+                                      .ok_or(error)?;
+                *slot = Some((target, level));
+            }
+            Some(_) => return Err(error),
+            None => log_level = setting.parse().map_err(|_| error)?,
+        }
+    }
+
+    config.log_level = log_level;
+    config.log_filters = log_filters;
+
+    Ok(())
+}
+
+/// Parses the framing in the style of Linux, e.g. `n8` for no parity and 8 data bits, optionally followed by the
+/// number of stop bits.
+fn parse_framing(framing: &str, line: &mut LineConfig) -> Option<()> {
+    let mut chars = framing.chars();
+
+    if let Some(parity) = chars.next() {
+        line.parity = match parity {
+            'n' => Parity::None,
+            'o' => Parity::Odd,
+            'e' => Parity::Even,
+            'm' => Parity::Mark,
+            's' => Parity::Space,
+            _ => return None,
+        };
+    }
+    if let Some(data_bits) = chars.next() {
+        line.data_bits = data_bits.to_digit(10)? as u8;
+    }
+    if let Some(stop_bits) = chars.next() {
+        line.stop_bits = match stop_bits {
+            '1' => StopBits::One,
+            '2' => StopBits::Two,
+            _ => return None,
+        };
+    }
+
+    chars.next().is_none().then_some(())
+}
+
+/// Parses the name of a serial port, e.g. `com2`, into its index.
+pub fn parse_port(name: &str) -> Option<usize> {
+    let port = name.strip_prefix("com")?.parse::<usize>().ok()?;
+
+    (1..=PORT_COUNT).contains(&port).then(|| port - 1)
+}
+
+fn parse_serial(value: &str) -> Result<SerialConfig, CmdlineError> {
+    let error = CmdlineError::InvalidValue("serial");
+
+    let (port, settings) = value.split_once(',').map_or((value, None), |(port, settings)| (port, Some(settings)));
+    let port = parse_port(port).ok_or(error)?;
+
+    let line = match settings {
+        Some(settings) => {
+            let digits = settings.find(|c: char| !c.is_ascii_digit()).unwrap_or(settings.len());
+            let (baud_rate, framing) = settings.split_at(digits);
+
+            let mut line = LineConfig { baud_rate: baud_rate.parse().map_err(|_| error)?, ..LineConfig::default() };
+            parse_framing(framing, &mut line).ok_or(error)?;
+
+            Some(line)
+        }
+        None => None,
+    };
+
+    Ok(SerialConfig { port, line })
+}
+
+/// Parses a command line of whitespace separated `key=value` and flag tokens.
+///
+/// Invalid values are reported, but do not stop the remaining tokens from being parsed. Tokens that are not known
+/// are ignored, e.g. the path of the kernel image, which some bootloaders put first.
+pub fn parse(command_line: &'static str) -> (Config, Option<CmdlineError>) {
+    let mut config = Config::DEFAULT;
+    let mut error = None;
+
+    for token in command_line.split_whitespace() {
+        let result = match token.split_once('=') {
+            Some(("loglevel", value)) => parse_log_level(value, &mut config),
+            Some(("serial", value)) => parse_serial(value).map(|serial| config.serial = Some(serial)),
+            Some(("gdb", value)) => {
+                parse_port(value).map(|port| config.gdb = Some(port)).ok_or(CmdlineError::InvalidValue("gdb"))
+            }
+            Some(("test", value)) => {
+                config.test = Some(value);
+                Ok(())
+            }
+            None if token == "noapic" => {
+                config.apic = false;
+                Ok(())
+            }
+            None if token == "exit_on_boot" => {
+                config.exit_on_boot = true;
+                Ok(())
+            }
+            _ => Ok(()),
+        };
+
+        if let Err(cause) = result {
+            error.get_or_insert(cause);
+        }
+    }
+
+    (config, error)
+}
+
+/// Returns the configuration of the kernel, which is the default one until the command line has been parsed.
+pub fn config() -> &'static Config {
+    CONFIG.get().unwrap_or(&Config::DEFAULT)
+}
+
+pub fn init(command_line: Option<&'static str>) -> Result<(), ()> {
+    let command_line = command_line.unwrap_or("");
+    let (config, error) = parse(command_line);
+    CONFIG.call_once(|| config);
+
+    aux::log::set_level(config.log_level);
+    for (target, level) in config.log_filters.iter().flatten() {
+        if aux::log::set_filter(target, *level).is_err() {
+            log::warn!("cmdline: cannot filter the log of `{}`", target);
+        }
+    }
+    log::info!("cmdline: `{}`", command_line);
+
+    // A typo on the command line must not keep the kernel from booting.
+    if let Some(CmdlineError::InvalidValue(key)) = error {
+        log::warn!("cmdline: invalid value for `{}`, keeping the default", key);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn flags_and_values() {
+        let (config, error) = parse("/boot/asmos.elf loglevel=warn noapic test=heap");
+        assert_eq!(error, None);
+        assert_eq!(config.log_level, LevelFilter::Warn);
+        assert_eq!(config.test, Some("heap"));
+        assert!(!config.apic && !config.exit_on_boot);
+        assert!(parse("exit_on_boot").0.exit_on_boot);
+    }
+
+    #[test_case]
+    fn log_filters() {
+        let (config, error) = parse("loglevel=warn,apic:trace,paging:off");
+        assert_eq!(error, None);
+        assert_eq!(config.log_level, LevelFilter::Warn);
+        assert_eq!(config.log_filters[..2], [Some(("apic", LevelFilter::Trace)), Some(("paging", LevelFilter::Off))]);
+
+        let (_, error) = parse("loglevel=apic:loud");
+        assert_eq!(error, Some(CmdlineError::InvalidValue("loglevel")));
+
+        // The entries before the invalid one are not applied either.
+        let (config, error) = parse("loglevel=warn,paging:off,apic:loud");
+        assert_eq!(error, Some(CmdlineError::InvalidValue("loglevel")));
+        assert_eq!(config.log_level, Config::DEFAULT.log_level);
+        assert_eq!(config.log_filters, Config::DEFAULT.log_filters);
+    }
+
+    #[test_case]
+    fn serial_settings() {
+        let (config, _) = parse("serial=com2,9600e7");
+        let line = LineConfig { baud_rate: 9600, parity: Parity::Even, data_bits: 7, ..LineConfig::default() };
+        assert_eq!(config.serial, Some(SerialConfig { port: 1, line: Some(line) }));
+
+        let (config, error) = parse("serial=com5 gdb=com2 noapic");
+        assert_eq!(error, Some(CmdlineError::InvalidValue("serial")));
+        assert!(config.serial.is_none() && !config.apic);
+        assert_eq!(config.gdb, Some(1));
+    }
+}
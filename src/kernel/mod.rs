@@ -20,14 +20,30 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-mod arch;
-
+pub mod arch;
+pub mod backtrace;
+pub mod cmdline;
+pub mod monitor;
+pub mod panic;
+pub mod power;
+pub mod qemu;
+pub mod recovery;
 pub mod serial;
 
 pub fn init(boot_info_addr: usize) {
     arch::init(boot_info_addr);
 }
 
+/// Starts accepting external interrupts; must not be called before the kernel is initialized.
+pub fn enable_interrupts() {
+    arch::enable_interrupts();
+}
+
+/// Stops accepting external interrupts, e.g. before the kernel halts for good.
+pub fn disable_interrupts() {
+    arch::disable_interrupts();
+}
+
 pub fn hlt_loop() -> ! {
     arch::hlt_loop();
 }
@@ -0,0 +1,255 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::arch;
+use super::power;
+use super::recovery;
+use super::serial::{self, Role};
+use crate::aux;
+use crate::{console_print, console_println};
+
+const PROMPT: &str = "asmos> ";
+const MAX_LINE_LENGTH: usize = 256;
+
+const CTRL_C: u8 = 0x03;
+const CTRL_U: u8 = 0x15;
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7F;
+const ESCAPE: u8 = 0x1B;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandError {
+    /// The arguments do not match the usage of the command.
+    Usage,
+    InvalidNumber,
+    Failed(&'static str),
+}
+
+/// Monitor Command
+///
+/// A command of the monitor, which is invoked with the words that follow its name on the line.
+pub struct Command {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub help: &'static str,
+    pub run: fn(&[&str]) -> Result<(), CommandError>,
+}
+
+/// Commands that do not depend on the architecture.
+const COMMANDS: &[Command] = &[
+    Command { name: "help", usage: "help", help: "list the commands", run: help },
+    Command {
+        name: "log",
+        usage: "log [<level> | <target> <level|clear>]",
+        help: "show or set the log level, overall or of a target",
+        run: log,
+    },
+    Command { name: "reboot", usage: "reboot", help: "reset the machine", run: reboot },
+    Command { name: "shutdown", usage: "shutdown", help: "power the machine off", run: shutdown },
+];
+
+fn commands() -> impl Iterator<Item = &'static Command> {
+    COMMANDS.iter().chain(arch::monitor::COMMANDS)
+}
+
+fn help(_args: &[&str]) -> Result<(), CommandError> {
+    for command in commands() {
+        console_println!("  {:<36} {}", command.usage, command.help);
+    }
+
+    Ok(())
+}
+
+fn log(args: &[&str]) -> Result<(), CommandError> {
+    let parse_level = |word: &str| word.parse().map_err(|_| CommandError::Failed("unknown log level"));
+
+    match *args {
+        [] => {
+            console_println!("  {:<36} {}", "*", aux::log::level());
+            aux::log::for_each_filter(|target, level| console_println!("  {:<36} {}", target, level));
+        }
+        [level] => aux::log::set_level(parse_level(level)?),
+        [target, "clear"] => {
+            if !aux::log::clear_filter(target) {
+                return Err(CommandError::Failed("no filter for this target"));
+            }
+        }
+        [target, level] => {
+            aux::log::set_filter(target, parse_level(level)?).map_err(|error| match error {
+                aux::log::FilterError::TooManyFilters => CommandError::Failed("too many filters"),
+                aux::log::FilterError::TargetTooLong => CommandError::Failed("target is too long"),
+            })?
+        }
+        _ => return Err(CommandError::Usage),
+    }
+
+    Ok(())
+}
+
+fn reboot(_args: &[&str]) -> Result<(), CommandError> {
+    power::reboot();
+}
+
+fn shutdown(_args: &[&str]) -> Result<(), CommandError> {
+    power::shutdown();
+}
+
+/// Parses a decimal number, or a hexadecimal one with a `0x` prefix.
+pub fn parse_number(word: &str) -> Result<u64, CommandError> {
+    let number = match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        Some(digits) => u64::from_str_radix(&digits.replace('_', ""), 16),
+        None => word.replace('_', "").parse(),
+    };
+
+    number.map_err(|_| CommandError::InvalidNumber)
+}
+
+/// Line Editor
+///
+/// Collects a line from the console, echoing what is typed. Supports backspace, Ctrl-U to clear the line, Ctrl-C to
+/// discard it and the up arrow to recall the previous line.
+struct LineEditor {
+    line: String,
+    previous: String,
+}
+
+impl LineEditor {
+    fn new() -> Self {
+        LineEditor { line: String::new(), previous: String::new() }
+    }
+
+    fn erase(&mut self) {
+        while self.line.pop().is_some() {
+            serial::write_bytes(Role::Console, &[BACKSPACE, b' ', BACKSPACE]);
+        }
+    }
+
+    /// Handles an escape sequence, of which only the up arrow (`ESC [ A`) is understood.
+    fn escape(&mut self) {
+        if serial::read_byte(Role::Console) != b'[' {
+            return;
+        }
+
+        if serial::read_byte(Role::Console) == b'A' {
+            self.erase();
+            self.line = self.previous.clone();
+            console_print!("{}", self.line);
+        }
+    }
+
+    fn read_line(&mut self) -> String {
+        console_print!("{}", PROMPT);
+
+        loop {
+            match serial::read_byte(Role::Console) {
+                b'\r' | b'\n' => {
+                    console_println!();
+
+                    let line = core::mem::take(&mut self.line);
+                    if !line.trim().is_empty() {
+                        self.previous = line.clone();
+                    }
+                    return line;
+                }
+                BACKSPACE | DELETE => {
+                    if self.line.pop().is_some() {
+                        serial::write_bytes(Role::Console, &[BACKSPACE, b' ', BACKSPACE]);
+                    }
+                }
+                CTRL_U => self.erase(),
+                CTRL_C => {
+                    console_println!("^C");
+                    self.line.clear();
+                    console_print!("{}", PROMPT);
+                }
+                ESCAPE => self.escape(),
+                byte @ 0x20..=0x7E if self.line.len() < MAX_LINE_LENGTH => {
+                    self.line.push(byte as char);
+                    serial::write_bytes(Role::Console, &[byte]);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Runs the command on the given line, if any.
+pub fn execute(line: &str) {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let Some((name, args)) = words.split_first() else {
+        return;
+    };
+
+    let Some(command) = commands().find(|command| command.name == *name) else {
+        console_println!("unknown command `{}`, see `help`", name);
+        return;
+    };
+
+    // A command that faults, e.g. by reading an MSR that does not exist, is aborted instead of halting the kernel.
+    let mut result = Ok(());
+    if let Some(event) = recovery::catch(|| result = (command.run)(args)) {
+        console_println!("{}: aborted by {:?}", command.name, event);
+        return;
+    }
+
+    match result {
+        Ok(()) => {}
+        Err(CommandError::Usage) => console_println!("usage: {}", command.usage),
+        Err(CommandError::InvalidNumber) => console_println!("{}: invalid number", command.name),
+        Err(CommandError::Failed(reason)) => console_println!("{}: {}", command.name, reason),
+    }
+}
+
+/// Kernel Monitor
+///
+/// An interactive shell on the console port to inspect and poke the running kernel; interrupts must be enabled.
+pub fn run() -> ! {
+    console_println!("asmOS monitor, type `help` for a list of commands");
+
+    let mut editor = LineEditor::new();
+    loop {
+        let line = editor.read_line();
+        execute(&line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn numbers_are_parsed() {
+        assert_eq!(parse_number("42"), Ok(42));
+        assert_eq!(parse_number("0xFFFF_FFFF_8000_0000"), Ok(0xFFFF_FFFF_8000_0000));
+        assert_eq!(parse_number("0xZ"), Err(CommandError::InvalidNumber));
+    }
+
+    #[test_case]
+    fn command_names_are_unique() {
+        for (index, command) in commands().enumerate() {
+            assert!(commands().skip(index + 1).all(|other| other.name != command.name));
+        }
+    }
+}
@@ -0,0 +1,86 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::Mutex;
+
+pub use super::arch::cpu::{CpuState, GeneralRegisters};
+use super::qemu::{self, ExitCode};
+use super::{backtrace, serial};
+use crate::serial_println;
+
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// The state of the processor at the fault that is about to be turned into a panic.
+static FAULT_STATE: Mutex<Option<CpuState>> = Mutex::new(None);
+
+/// Hands the state of the processor over to the report of the panic that is about to be raised, e.g. by an
+/// exception handler that knows the state of the faulting code instead of its own.
+pub fn set_cpu_state(state: CpuState) {
+    if let Some(mut fault_state) = FAULT_STATE.try_lock() {
+        *fault_state = Some(state);
+    }
+}
+
+/// Prints the location and message of the panic, the state of the processor and the stack trace.
+///
+/// The report does not depend on the serial port being unlocked, since the panicking code may well have held the
+/// lock. If the kernel panics again while the report is printed, the nested panic is reported in a single line and
+/// the kernel stops right away.
+pub fn report(panic_info: &PanicInfo) {
+    // An interrupt handler must neither print over the report nor take the serial lock while it is printed.
+    super::disable_interrupts();
+
+    // Whoever holds the lock will never run again to release it.
+    unsafe { serial::force_unlock(); }
+
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        match panic_info.location() {
+            Some(location) => serial_println!("nested panic at {}: {}", location, panic_info.message()),
+            None => serial_println!("nested panic: {}", panic_info.message()),
+        }
+
+        qemu::exit(ExitCode::Failed);
+        super::hlt_loop();
+    }
+
+    let state = FAULT_STATE.try_lock().and_then(|mut state| state.take()).unwrap_or_else(CpuState::capture);
+
+    match panic_info.location() {
+        Some(location) => {
+            serial_println!("kernel panicked at {}:{}:{}", location.file(), location.line(), location.column());
+        }
+        None => serial_println!("kernel panicked at an unknown location"),
+    }
+    serial_println!("{}", panic_info.message());
+    serial_println!();
+    serial_println!("{}", state);
+    serial_println!();
+
+    // The trace of a fault starts at the faulting code, not at the exception handler that raised the panic.
+    match state.registers {
+        Some(registers) => backtrace::print_from(registers.rbp),
+        None => backtrace::print(),
+    }
+}
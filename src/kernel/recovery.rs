@@ -0,0 +1,102 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::Mutex;
+
+use super::arch::recovery::RecoveryPoint;
+
+/// Something that can cut a call made through `catch` short, or that it can observe, instead of it halting the
+/// kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Panic,
+    Exception { vector: u8, error_code: Option<u64> },
+}
+
+static CATCHING: AtomicBool = AtomicBool::new(false);
+static mut RECOVERY_POINT: RecoveryPoint = RecoveryPoint::new();
+
+static FATAL_EVENT: Mutex<Option<Event>> = Mutex::new(None);
+static LAST_EVENT: Mutex<Option<Event>> = Mutex::new(None);
+
+/// Runs `f` and returns the fatal event that cut it short, or `None` if it returned.
+///
+/// While `f` runs, panics and fatal exceptions resume right here instead of halting the kernel. Whatever `f` owned
+/// at that point is leaked.
+pub fn catch<F: FnOnce()>(f: F) -> Option<Event> {
+    assert!(!CATCHING.swap(true, Ordering::SeqCst), "recovery::catch must not be nested");
+    *FATAL_EVENT.lock() = None;
+    *LAST_EVENT.lock() = None;
+
+    let returned = unsafe { RecoveryPoint::call(ptr::addr_of_mut!(RECOVERY_POINT), f) };
+    CATCHING.store(false, Ordering::SeqCst);
+
+    if returned { None } else { FATAL_EVENT.lock().take() }
+}
+
+/// Returns the last non-fatal event that was recorded during the last `catch`.
+pub fn take_event() -> Option<Event> {
+    LAST_EVENT.lock().take()
+}
+
+/// Records a non-fatal event, e.g. a breakpoint, for the call that is currently caught.
+pub fn record(event: Event) {
+    if CATCHING.load(Ordering::SeqCst) {
+        *LAST_EVENT.lock() = Some(event);
+    }
+}
+
+/// Hands a fatal event to the call that is currently caught, which never returns. Returns if nothing is caught.
+pub fn resume(event: Event) {
+    if CATCHING.swap(false, Ordering::SeqCst) {
+        *FATAL_EVENT.lock() = Some(event);
+
+        unsafe { RecoveryPoint::resume(ptr::addr_of!(RECOVERY_POINT)); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test_case]
+    fn catch_returns_none_without_events() {
+        assert_eq!(catch(|| {}), None);
+        assert_eq!(take_event(), None);
+    }
+
+    #[test_case]
+    fn panics_are_caught() {
+        testing::should_panic(|| panic!("expected"));
+    }
+
+    #[test_case]
+    fn catching_again_after_a_panic() {
+        testing::should_panic(|| panic!("first"));
+        testing::should_panic(|| panic!("second"));
+        assert_eq!(catch(|| {}), None);
+    }
+}
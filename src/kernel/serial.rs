@@ -24,11 +24,83 @@ use core::fmt::Arguments;
 
 use super::arch;
 
+pub use super::arch::serial::{Role, SerialError, PORT_COUNT};
+pub use super::arch::uart::{LineConfig, Parity, StopBits};
+
 #[doc(hidden)]
 pub fn _print(args: Arguments) {
     arch::serial::_print(args);
 }
 
+/// Prints to the serial port of the given role.
+pub fn write_fmt(role: Role, args: Arguments) {
+    arch::serial::write_fmt(role, args);
+}
+
+/// Sends the given bytes as they are to the serial port of the given role.
+pub fn write_bytes(role: Role, bytes: &[u8]) {
+    arch::serial::write_bytes(role, bytes);
+}
+
+/// Returns `true` if the serial port with the given index, counted from 0 for COM1, exists.
+pub fn is_present(port: usize) -> bool {
+    arch::serial::is_present(port)
+}
+
+/// Returns the index of the serial port that the given role sits on.
+pub fn port_of(role: Role) -> usize {
+    arch::serial::port_of(role)
+}
+
+/// Moves the given role to the serial port with the given index.
+pub fn assign(role: Role, port: usize) -> Result<(), SerialError> {
+    arch::serial::assign(role, port)
+}
+
+/// Changes the baud rate and framing of the serial port with the given index.
+pub fn configure(port: usize, config: &LineConfig) -> Result<(), SerialError> {
+    arch::serial::configure(port, config)
+}
+
+/// Returns the next byte that was received on the serial port of the given role, without waiting for one.
+pub fn try_read_byte(role: Role) -> Option<u8> {
+    arch::serial::try_read_byte(role)
+}
+
+/// Waits for the next byte on the serial port of the given role; interrupts must be enabled.
+pub fn read_byte(role: Role) -> u8 {
+    arch::serial::read_byte(role)
+}
+
+/// Waits until at least one byte was received on the serial port of the given role, then reads as many as are
+/// available and fit into the buffer. Returns the number of bytes read.
+pub fn read(role: Role, buf: &mut [u8]) -> usize {
+    let Some((first, rest)) = buf.split_first_mut() else {
+        return 0;
+    };
+    *first = read_byte(role);
+
+    let mut count = 1;
+    for byte in rest {
+        match arch::serial::try_read_byte(role) {
+            Some(received) => *byte = received,
+            None => break,
+        }
+        count += 1;
+    }
+
+    count
+}
+
+/// Releases the locks on the serial ports, so that a panic can be reported while one of them is held.
+///
+/// # Safety
+///
+/// The holders of the locks must never run again.
+pub unsafe fn force_unlock() {
+    arch::serial::force_unlock();
+}
+
 #[macro_export]
 macro_rules! serial_print {
     ($($arg:tt)*) => ($crate::kernel::serial::_print(format_args!($($arg)*)));
@@ -40,3 +112,17 @@ macro_rules! serial_println {
     ($fmt:expr) => ($crate::serial_print!(concat!($fmt, "\n")));
     ($fmt:expr, $($arg:tt)*) => ($crate::serial_print!(concat!($fmt, "\n"), $($arg)*));
 }
+
+#[macro_export]
+macro_rules! console_print {
+    ($($arg:tt)*) => ($crate::kernel::serial::write_fmt(
+        $crate::kernel::serial::Role::Console, format_args!($($arg)*)
+    ));
+}
+
+#[macro_export]
+macro_rules! console_println {
+    () => ($crate::console_print!("\n"));
+    ($fmt:expr) => ($crate::console_print!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => ($crate::console_print!(concat!($fmt, "\n"), $($arg)*));
+}
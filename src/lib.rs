@@ -21,16 +21,45 @@
 // SOFTWARE.
 
 #![no_std]
+#![cfg_attr(test, no_main)]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
+#![feature(custom_test_frameworks)]
+#![test_runner(crate::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+// Initialization routines return `Result<(), ()>` and are unwrapped with `expect` at boot.
+#![allow(clippy::result_unit_err)]
+
+extern crate alloc;
 
 mod aux;
 pub mod kernel;
+pub mod testing;
 
 pub fn init(boot_info_addr: usize) {
     aux::init();
     kernel::init(boot_info_addr);
 }
 
+pub fn enable_interrupts() {
+    kernel::enable_interrupts();
+}
+
 pub fn hlt_loop() -> ! {
     kernel::hlt_loop();
 }
+
+#[cfg(test)]
+#[no_mangle]
+pub extern "C" fn k_main(boot_info_addr: usize) -> ! {
+    init(boot_info_addr);
+    test_main();
+
+    hlt_loop();
+}
+
+#[cfg(test)]
+#[panic_handler]
+fn on_panic(panic_info: &core::panic::PanicInfo) -> ! {
+    testing::on_panic(panic_info);
+}
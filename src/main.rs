@@ -23,22 +23,39 @@
 #![no_std]
 #![no_main]
 #![feature(lang_items)]
-#![feature(panic_info_message)]
+#![allow(internal_features)]
 
 use core::panic::PanicInfo;
 
-use asmos::serial_println;
+use asmos::kernel::cmdline;
+use asmos::kernel::monitor;
+use asmos::kernel::panic;
+use asmos::kernel::qemu::{self, ExitCode};
+use asmos::kernel::recovery::{self, Event};
 
 #[no_mangle]
 pub extern "C" fn k_main(boot_info_addr: usize) -> ! {
     asmos::init(boot_info_addr);
+    asmos::enable_interrupts();
 
-    asmos::hlt_loop();
+    // Headless runs only check that the kernel boots, interactive runs drop into the monitor. The exit port is only
+    // written when asked for, since outside of QEMU it may belong to any device.
+    if cmdline::config().exit_on_boot {
+        qemu::exit(ExitCode::Success);
+    }
+
+    monitor::run();
 }
 
 #[panic_handler]
 fn on_panic(panic_info: &PanicInfo) -> ! {
-    serial_println!("{:#?}", panic_info.message());
+    recovery::resume(Event::Panic);
+
+    panic::report(panic_info);
+
+    if cmdline::config().exit_on_boot {
+        qemu::exit(ExitCode::Failed);
+    }
 
     asmos::hlt_loop();
 }
@@ -0,0 +1,93 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use core::panic::PanicInfo;
+
+use crate::kernel::cmdline;
+use crate::kernel::panic;
+use crate::kernel::qemu::{self, ExitCode};
+use crate::kernel::recovery::{self, Event};
+use crate::kernel::serial;
+use crate::{serial_print, serial_println};
+
+/// A test that reports its own name and result over the serial port.
+pub trait Testable {
+    fn name(&self) -> &'static str;
+
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn name(&self) -> &'static str {
+        core::any::type_name::<T>()
+    }
+
+    fn run(&self) {
+        serial_print!("{}...\t", self.name());
+        self();
+        serial_println!("[ok]");
+    }
+}
+
+/// Runs every `#[test_case]` in order and exits QEMU once all of them passed.
+pub fn test_runner(tests: &[&dyn Testable]) {
+    // `test=<filter>` on the kernel command line runs only the tests whose name contains the filter.
+    let filter = cmdline::config().test.unwrap_or("");
+    let count = tests.iter().filter(|test| test.name().contains(filter)).count();
+    serial_println!("running {} of {} tests", count, tests.len());
+
+    for test in tests.iter().filter(|test| test.name().contains(filter)) {
+        test.run();
+    }
+
+    qemu::exit(ExitCode::Success);
+}
+
+/// Asserts that `f` panics.
+pub fn should_panic<F: FnOnce()>(f: F) {
+    match recovery::catch(f) {
+        Some(Event::Panic) => {}
+        other => panic!("expected a panic, got {:?}", other),
+    }
+}
+
+/// Asserts that `f` raises the exception with the given vector and returns its error code.
+pub fn expect_exception<F: FnOnce()>(vector: u8, f: F) -> Option<u64> {
+    match recovery::catch(f) {
+        Some(Event::Exception { vector: raised, error_code }) if raised == vector => error_code,
+        other => panic!("expected exception {:#04X}, got {:?}", vector, other),
+    }
+}
+
+/// Reports the panicking test as failed and exits QEMU, unless the test expected the panic.
+pub fn on_panic(panic_info: &PanicInfo) -> ! {
+    recovery::resume(Event::Panic);
+
+    // The test may have panicked while printing its name.
+    unsafe { serial::force_unlock(); }
+    serial_println!("[failed]");
+    panic::report(panic_info);
+
+    qemu::exit(ExitCode::Failed);
+
+    crate::hlt_loop();
+}
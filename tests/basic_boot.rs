@@ -0,0 +1,62 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(asmos::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+
+use asmos::serial_println;
+
+#[no_mangle]
+pub extern "C" fn k_main(boot_info_addr: usize) -> ! {
+    asmos::init(boot_info_addr);
+    test_main();
+
+    asmos::hlt_loop();
+}
+
+#[panic_handler]
+fn on_panic(panic_info: &PanicInfo) -> ! {
+    asmos::testing::on_panic(panic_info);
+}
+
+#[test_case]
+fn serial_println_after_init() {
+    serial_println!("serial output after init");
+}
+
+#[test_case]
+fn heap_allocations_after_init() {
+    let boxed = Box::new(41);
+    assert_eq!(*boxed + 1, 42);
+
+    let values: Vec<u64> = (0..1000).collect();
+    assert_eq!(values.iter().sum::<u64>(), 999 * 1000 / 2);
+}
@@ -0,0 +1,92 @@
+// MIT License
+//
+// Copyright (c) 2023 Mansoor Ahmed Memon.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use core::arch::asm;
+use core::panic::PanicInfo;
+
+use lazy_static::lazy_static;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use x86_64::VirtAddr;
+
+use asmos::kernel::arch::exceptions::DoubleFaultException;
+use asmos::kernel::arch::gdt;
+use asmos::kernel::qemu::{self, ExitCode};
+use asmos::{serial_print, serial_println};
+
+lazy_static! {
+    /// Only handles double faults, so that the page fault raised by the overflow cannot be delivered either.
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+
+        unsafe {
+            idt.double_fault.set_handler_fn(on_double_fault)
+                            .set_stack_index(DoubleFaultException::IST_INDEX as u16);
+        }
+
+        idt
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn k_main(boot_info_addr: usize) -> ! {
+    serial_print!("stack_overflow::stack_overflow...\t");
+
+    asmos::init(boot_info_addr);
+    TEST_IDT.load();
+
+    stack_overflow();
+
+    panic!("execution continued after stack overflow");
+}
+
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+    stack_overflow();
+
+    // Prevent the recursion from being turned into a loop.
+    unsafe { core::ptr::read_volatile(&0u8); }
+}
+
+extern "x86-interrupt" fn on_double_fault(_stack_frame: InterruptStackFrame, _error_code: u64) -> ! {
+    let rsp: u64;
+    unsafe { asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack)); }
+
+    if gdt::interrupt_stack(DoubleFaultException::IST_INDEX).contains(&VirtAddr::new(rsp)) {
+        serial_println!("[ok]");
+        qemu::exit(ExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        serial_println!("double fault handler runs on RSP={:#X}, outside of its IST stack", rsp);
+        qemu::exit(ExitCode::Failed);
+    }
+
+    asmos::hlt_loop();
+}
+
+#[panic_handler]
+fn on_panic(panic_info: &PanicInfo) -> ! {
+    asmos::testing::on_panic(panic_info);
+}